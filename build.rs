@@ -1,11 +1,18 @@
-extern crate bindgen;
+// Without the bindgen feature the pre-generated bindings in src/bindings are used, and there is
+// nothing to build.
+#[cfg(not(feature = "bindgen"))]
+fn main() {}
 
+#[cfg(feature = "bindgen")]
 use std::env;
+#[cfg(feature = "bindgen")]
 use std::path::PathBuf;
 
 // Clang extra args env variable name
+#[cfg(feature = "bindgen")]
 const MOSQUITTO_PLUGIN_CLANG_EXTRA_ARGS: &str = "MOSQUITTO_PLUGIN_CLANG_EXTRA_ARGS";
 
+#[cfg(feature = "bindgen")]
 fn main() {
     // Tell cargo to invalidate the built crate whenever the wrapper changes
     println!("cargo:rerun-if-changed=wrapper.h");
@@ -1,5 +1,3 @@
-#![allow(clippy::not_unsafe_ptr_arg_deref)]
-
 // Has to be included, to get the errors and success parameters that are used in the
 // generate_dynamic_library macro invocation
 use mosquitto_plugin::*;
@@ -14,27 +12,39 @@ pub struct Test {
 
 // Required trait implementation
 impl MosquittoPlugin for Test {
-    fn init(opts: std::collections::HashMap<&str, &str>) -> Self {
+    // Only the callbacks implemented below are registered with mosquitto
+    const EVENTS: EventSet = EventSet::BASIC_AUTH
+        .union(EventSet::ACL_CHECK)
+        .union(EventSet::DISCONNECT)
+        .union(EventSet::MESSAGE);
+
+    fn init(opts: std::collections::HashMap<&str, &str>) -> Result<Self, InitError> {
         // These are the strings provided after "auth_opt_<key> value" in the mosquitto.conf
         // only that they are provided on a hashmap form here
-        let default = "hej";
-        let topic = opts.get("topic").unwrap_or(&default);
-        let level = opts.get("level").unwrap_or(&default);
-        let level = level.parse().unwrap_or(0);
+        let topic = opts.get("topic").unwrap_or(&"hej");
+        let level = opts.get("level").unwrap_or(&"0");
+        // A level that isn't a number keeps the broker from starting
+        let level = level.parse().map_err(|_| {
+            InitError::invalid_options(format!("level {:?} is not a number", level))
+        })?;
 
-        Test {
+        Ok(Test {
             i: level,
             s: topic.to_string(),
-        }
+        })
     }
 
     fn username_password(
         &mut self,
         client: &dyn MosquittoClientContext,
-        u: Option<&str>,
-        p: Option<&str>,
+        _listener: Option<Listener>,
+        u: Option<&[u8]>,
+        p: Option<&[u8]>,
     ) -> Result<Success, Error> {
         let client_id = client.get_id().unwrap_or_else(|| "unknown".into());
+        // Usernames and passwords are binary, this example only accepts text ones
+        let u = u.and_then(|u| std::str::from_utf8(u).ok());
+        let p = p.and_then(|p| std::str::from_utf8(p).ok());
         mosquitto_debug!("USERNAME_PASSWORD({}) {:?} - {:?}", client_id, u, p);
         if u.is_none() || p.is_none() {
             return Err(Error::Auth);
@@ -76,6 +86,7 @@ impl MosquittoPlugin for Test {
     fn acl_check(
         &mut self,
         _client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
         level: AclCheckAccessLevel,
         msg: MosquittoMessage,
     ) -> Result<Success, mosquitto_plugin::Error> {
@@ -93,20 +104,26 @@ impl MosquittoPlugin for Test {
         }
     }
 
-    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, _reason: i32) {
+    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, reason: DisconnectReason) {
         mosquitto_info!(
-            "Plugin on_disconnect, Client {:?} is disconnecting",
-            client.get_id()
+            "Plugin on_disconnect, Client {:?} is disconnecting: {:?}",
+            client.get_id(),
+            reason
         );
     }
 
-    fn on_message(&mut self, client: &dyn MosquittoClientContext, message: MosquittoMessage) {
+    fn on_message(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        message: MosquittoMessage,
+    ) -> MessageAction {
         mosquitto_info!(
             "Plugin on_message: client {:?}: Topic: {}, Payload: {:?}",
             client.get_id(),
             message.topic,
             message.payload
-        )
+        );
+        MessageAction::Pass
     }
 }
 
@@ -1,5 +1,3 @@
-#![allow(clippy::not_unsafe_ptr_arg_deref)]
-
 use mosquitto_plugin::*;
 use std::collections::HashMap;
 
@@ -15,42 +13,54 @@ pub struct AuthPlugin;
 
 // Required trait implementation
 impl MosquittoPlugin for AuthPlugin {
-    fn init(_opts: HashMap<&str, &str>) -> Self {
-        AuthPlugin
+    const EVENTS: EventSet = EventSet::EXT_AUTH.union(EventSet::DISCONNECT);
+
+    fn init(_opts: HashMap<&str, &str>) -> Result<Self, InitError> {
+        Ok(AuthPlugin)
     }
 
-    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, _reason: i32) {
+    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, reason: DisconnectReason) {
         mosquitto_info!(
-            "Plugin on_disconnect, Client {:?} is disconnecting",
-            client.get_id()
+            "Plugin on_disconnect, Client {:?} is disconnecting: {:?}",
+            client.get_id(),
+            reason
         );
     }
 
-    /// Authentication start.
+    /// Authentication start, on connect and re-authentication.
     fn on_auth_start(
         &mut self,
         client: &dyn MosquittoClientContext,
-        method: Option<&str>,
-        data: Option<&[u8]>,
-    ) -> Result<Success, Error> {
+        _listener: Option<Listener>,
+        auth: AuthStart,
+    ) -> AuthOutcome {
         mosquitto_info!(
-            "Plugin on_auth_start, Client {:?} with method {:?} and data {:?}",
+            "Plugin on_auth_start, Client {:?} with method {:?} and data {:?}, reauth {}",
             client.get_id(),
-            method,
-            data
+            auth.method,
+            auth.data,
+            auth.reauth
         );
+        // A client that already greeted back doesn't have to again
+        if auth.reauth {
+            return AuthOutcome::Accept(None);
+        }
         // Sending random auth data "hello"
-        Err(Error::AuthContinue(HELLO_CLIENT.as_bytes().to_vec()))
+        AuthOutcome::Continue(HELLO_CLIENT.as_bytes().to_vec())
     }
 
     /// Authentication continue.
     fn on_auth_continue(
         &mut self,
         client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
         method: Option<&str>,
         data: Option<&[u8]>,
-    ) -> Result<Success, Error> {
-        let client_id = client.get_id().ok_or(Error::Inval)?;
+    ) -> AuthOutcome {
+        let client_id = match client.get_id() {
+            Some(client_id) => client_id,
+            None => return AuthOutcome::Deny,
+        };
         mosquitto_info!(
             "Plugin on_auth_continue, Client {} with method {:?} and data {:?}",
             client_id,
@@ -59,23 +69,19 @@ impl MosquittoPlugin for AuthPlugin {
         );
 
         // If client replies with "hello broker" we're fine - otherwise greet again.
-        if let Some(data) = data {
-            if data == HELLO_BROKER.as_bytes() {
-                mosquitto_info!(
-                    "Plugin on_auth_continue, Client {} authenticated",
-                    client_id
-                );
-                Ok(Success)
-            } else {
-                mosquitto_warn!(
-                    "Plugin on_auth_continue, Client {} failed to authenticate. Expected \"{}\"",
-                    client_id,
-                    HELLO_BROKER,
-                );
-                Err(Error::AuthContinue(HELLO_CLIENT.as_bytes().to_vec()))
-            }
+        if data == Some(HELLO_BROKER.as_bytes()) {
+            mosquitto_info!(
+                "Plugin on_auth_continue, Client {} authenticated",
+                client_id
+            );
+            AuthOutcome::Accept(None)
         } else {
-            Err(Error::AuthContinue(HELLO_CLIENT.as_bytes().to_vec()))
+            mosquitto_warn!(
+                "Plugin on_auth_continue, Client {} failed to authenticate. Expected \"{}\"",
+                client_id,
+                HELLO_BROKER,
+            );
+            AuthOutcome::Continue(HELLO_CLIENT.as_bytes().to_vec())
         }
     }
 }
@@ -0,0 +1,62 @@
+// Serves the keys of a TLS-PSK listener from a file in the format of the mosquitto psk_file,
+// one `identity:key` per line with the key hex encoded. The file is read on startup and again on
+// reload, a file that doesn't parse keeps the broker from starting, or the keys read before.
+
+use mosquitto_plugin::*;
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct PskFile {
+    keys: HashMap<String, PskKey>,
+}
+
+impl MosquittoPlugin for PskFile {
+    const EVENTS: EventSet = EventSet::PSK_KEY.union(EventSet::RELOAD);
+    const RELOAD_POLICY: ReloadPolicy = ReloadPolicy::Rebuild;
+
+    fn init(opts: HashMap<&str, &str>) -> Result<Self, InitError> {
+        let path = opts
+            .get("psk_file")
+            .ok_or_else(|| InitError::invalid_options("plugin_opt_psk_file is not set"))?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| InitError::new(Error::Errno, format!("{}: {}", path, e)))?;
+
+        let mut keys = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (identity, key) = line
+                .split_once(':')
+                .and_then(|(identity, key)| Some((identity, PskKey::from_hex(key)?)))
+                .ok_or_else(|| {
+                    InitError::invalid_options(format!(
+                        "{}:{}: expected identity:hexkey",
+                        path,
+                        number + 1
+                    ))
+                })?;
+            keys.insert(identity.to_string(), key);
+        }
+        mosquitto_info!("read {} PSK keys from {}", keys.len(), path);
+        Ok(PskFile { keys })
+    }
+
+    fn on_psk(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _hint: &std::ffi::CStr,
+        identity: &std::ffi::CStr,
+    ) -> Option<PskKey> {
+        // Unknown identities are left to other plugins and the psk_file of the listener
+        let key = self.keys.get(identity.to_str().ok()?).cloned();
+        if key.is_none() {
+            mosquitto_debug!("no PSK key for {:?}", identity);
+        }
+        key
+    }
+}
+
+// This generates the dynamic c bindings functions that are exported and usable by mosquitto
+create_dynamic_library!(PskFile);
@@ -0,0 +1,92 @@
+// Authenticates clients with SCRAM-SHA-256 over MQTT v5 AUTH, with credentials from a file of
+// `username:credentials` lines, the credentials in the RFC 5803 format Credentials::parse takes.
+// The file is read on startup and again on reload, a file that doesn't parse keeps the broker
+// from starting, or the credentials read before.
+
+use mosquitto_plugin::auth::scram::{Credentials, Scram};
+use mosquitto_plugin::*;
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct ScramAuth {
+    scram: Scram<HashMap<String, Credentials>>,
+}
+
+fn read_credentials(opts: &HashMap<&str, &str>) -> Result<HashMap<String, Credentials>, InitError> {
+    let path = opts
+        .get("credentials_file")
+        .ok_or_else(|| InitError::invalid_options("plugin_opt_credentials_file is not set"))?;
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| InitError::new(Error::Errno, format!("{}: {}", path, e)))?;
+
+    let mut users = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (username, credentials) = line
+            .split_once(':')
+            .and_then(|(username, credentials)| Some((username, Credentials::parse(credentials)?)))
+            .ok_or_else(|| {
+                InitError::invalid_options(format!(
+                    "{}:{}: expected username:SCRAM-SHA-256$...",
+                    path,
+                    number + 1
+                ))
+            })?;
+        users.insert(username.to_string(), credentials);
+    }
+    mosquitto_info!(
+        "read the credentials of {} users from {}",
+        users.len(),
+        path
+    );
+    Ok(users)
+}
+
+impl MosquittoPlugin for ScramAuth {
+    const EVENTS: EventSet = EventSet::EXT_AUTH
+        .union(EventSet::DISCONNECT)
+        .union(EventSet::RELOAD);
+
+    fn init(opts: HashMap<&str, &str>) -> Result<Self, InitError> {
+        Ok(ScramAuth {
+            scram: Scram::new(read_credentials(&opts)?),
+        })
+    }
+
+    // Swaps the credentials only, conversations of connecting clients go on
+    fn on_reload(&mut self, opts: HashMap<&str, &str>) {
+        match read_credentials(&opts) {
+            Ok(users) => *self.scram.store_mut() = users,
+            Err(e) => mosquitto_warn!("keeping the previous credentials: {}", e),
+        }
+    }
+
+    fn on_auth_start(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
+        auth: AuthStart,
+    ) -> AuthOutcome {
+        self.scram.on_auth_start(client, auth)
+    }
+
+    fn on_auth_continue(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
+        method: Option<&str>,
+        data: Option<&[u8]>,
+    ) -> AuthOutcome {
+        self.scram.on_auth_continue(client, method, data)
+    }
+
+    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, _reason: DisconnectReason) {
+        self.scram.on_disconnect(client)
+    }
+}
+
+// This generates the dynamic c bindings functions that are exported and usable by mosquitto
+create_dynamic_library!(ScramAuth);
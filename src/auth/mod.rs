@@ -0,0 +1,4 @@
+// Extended authentication mechanisms, driven from the on_auth_start, on_auth_continue and
+// on_disconnect callbacks of a plugin.
+
+pub mod scram;
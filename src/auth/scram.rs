@@ -0,0 +1,623 @@
+// SCRAM-SHA-256 (RFC 5802, RFC 7677) over MQTT v5 extended authentication.
+//
+// The client sends its client-first message with the CONNECT packet and the auth method
+// "SCRAM-SHA-256". The broker answers with the server-first message, salt and iteration count in
+// an AUTH packet, the client proves it knows the password with the client-final message, and the
+// broker accepts with the server-final message in the CONNACK, proving it knows the keys as well.
+// The password never crosses the connection, and the broker only keeps keys derived from it.
+//
+// Channel binding isn't supported, clients asking for it are denied. Passwords and usernames
+// aren't normalized with SASLprep, which makes no difference for ASCII.
+
+use crate::{mosquitto_warn, AuthOutcome, AuthStart, MosquittoClientContext};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::BuildHasher;
+
+/// The auth method of SCRAM-SHA-256
+pub const METHOD: &str = "SCRAM-SHA-256";
+
+// The iteration count answered for unknown users, the minimum RFC 7677 asks for
+const UNKNOWN_USER_ITERATIONS: u32 = 4096;
+
+/// The keys derived from a password, which the broker stores instead of it
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: [u8; 32],
+    pub server_key: [u8; 32],
+}
+
+impl Credentials {
+    /// Derives the keys from a password. The salt should be random and at least 16 bytes, the
+    /// iterations at least 4096.
+    pub fn derive(password: &[u8], salt: &[u8], iterations: u32) -> Credentials {
+        let mut salted_password = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut salted_password);
+        let client_key = hmac(&salted_password, b"Client Key");
+        Credentials {
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: Sha256::digest(client_key).into(),
+            server_key: hmac(&salted_password, b"Server Key"),
+        }
+    }
+
+    /// Parses credentials in the format of RFC 5803,
+    /// `SCRAM-SHA-256$<iterations>:<salt>$<stored key>:<server key>` with base64 salt and keys
+    pub fn parse(s: &str) -> Option<Credentials> {
+        let (iterations, salt) = s.strip_prefix("SCRAM-SHA-256$")?.split_once(':')?;
+        let (salt, keys) = salt.split_once('$')?;
+        let (stored_key, server_key) = keys.split_once(':')?;
+        Some(Credentials {
+            salt: BASE64.decode(salt).ok()?,
+            iterations: iterations.parse().ok().filter(|&i| i > 0)?,
+            stored_key: BASE64.decode(stored_key).ok()?.try_into().ok()?,
+            server_key: BASE64.decode(server_key).ok()?.try_into().ok()?,
+        })
+    }
+}
+
+// The format parse takes
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SCRAM-SHA-256${}:{}${}:{}",
+            self.iterations,
+            BASE64.encode(&self.salt),
+            BASE64.encode(self.stored_key),
+            BASE64.encode(self.server_key)
+        )
+    }
+}
+
+// The keys stay out of logs, they log in to this broker as well as the password
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Credentials({} iterations)", self.iterations)
+    }
+}
+
+/// Looks up the credentials of a user, None for unknown users
+pub trait CredentialStore {
+    fn credentials(&self, username: &str) -> Option<Credentials>;
+}
+
+impl<S: BuildHasher> CredentialStore for HashMap<String, Credentials, S> {
+    fn credentials(&self, username: &str) -> Option<Credentials> {
+        self.get(username).cloned()
+    }
+}
+
+// A conversation waiting for the client-final message
+struct Conversation {
+    username: String,
+    gs2_header: String,
+    client_first_bare: String,
+    server_first: String,
+    nonce: String,
+    // None for unknown users, which are denied after the proof like a wrong password
+    credentials: Option<Credentials>,
+}
+
+/// SCRAM-SHA-256 authentication of clients, with the credentials of `S`.
+///
+/// The plugin keeps it in a field and hands the extended auth and disconnect events to it,
+/// registering for `EventSet::EXT_AUTH` and `EventSet::DISCONNECT`. Conversations are kept per
+/// client id until the client is accepted, denied or disconnects. An accepted client gets the
+/// SCRAM username as its username, for the ACL checks.
+///
+/// ```
+/// use mosquitto_plugin::auth::scram::{Credentials, Scram};
+/// use mosquitto_plugin::*;
+/// use std::collections::HashMap;
+///
+/// #[derive(Debug)]
+/// struct Plugin {
+///     scram: Scram<HashMap<String, Credentials>>,
+/// }
+///
+/// impl MosquittoPlugin for Plugin {
+///     const EVENTS: EventSet = EventSet::EXT_AUTH.union(EventSet::DISCONNECT);
+///
+///     fn init(_opts: MosquittoOpt) -> Result<Self, InitError> {
+///         let mut users = HashMap::new();
+///         users.insert(
+///             "sensor".to_string(),
+///             Credentials::derive(b"secret", b"random salt", 4096),
+///         );
+///         Ok(Plugin {
+///             scram: Scram::new(users),
+///         })
+///     }
+///
+///     fn on_auth_start(
+///         &mut self,
+///         client: &dyn MosquittoClientContext,
+///         _listener: Option<Listener>,
+///         auth: AuthStart,
+///     ) -> AuthOutcome {
+///         self.scram.on_auth_start(client, auth)
+///     }
+///
+///     fn on_auth_continue(
+///         &mut self,
+///         client: &dyn MosquittoClientContext,
+///         _listener: Option<Listener>,
+///         method: Option<&str>,
+///         data: Option<&[u8]>,
+///     ) -> AuthOutcome {
+///         self.scram.on_auth_continue(client, method, data)
+///     }
+///
+///     fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, _: DisconnectReason) {
+///         self.scram.on_disconnect(client)
+///     }
+/// }
+/// ```
+pub struct Scram<S> {
+    store: S,
+    conversations: HashMap<String, Conversation>,
+    // Keys the salts made up for unknown users, so they stay the same between attempts and
+    // can't be told from those of known users
+    secret: Option<[u8; 32]>,
+    // The random part of the nonce, fixed in tests
+    nonce: fn() -> Option<String>,
+}
+
+impl<S: CredentialStore> Scram<S> {
+    pub fn new(store: S) -> Scram<S> {
+        Scram {
+            store,
+            conversations: HashMap::new(),
+            secret: None,
+            nonce: random_nonce,
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// The store, to update the credentials on reload. Running conversations keep the
+    /// credentials they started with.
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// Answers the client-first message with the server-first message. Other auth methods are
+    /// deferred, a started conversation of the client is dropped.
+    pub fn on_auth_start(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        auth: AuthStart,
+    ) -> AuthOutcome {
+        if auth.method != Some(METHOD) {
+            return AuthOutcome::Defer;
+        }
+        let client_id = match client.get_id() {
+            Some(client_id) => client_id,
+            None => return AuthOutcome::Deny,
+        };
+        self.conversations.remove(&client_id);
+
+        let client_first = match auth.data.and_then(|data| std::str::from_utf8(data).ok()) {
+            Some(client_first) => client_first,
+            None => {
+                mosquitto_warn!("SCRAM client {} sent no client-first message", client_id);
+                return AuthOutcome::Deny;
+            }
+        };
+        let (gs2_header, client_first_bare, username, client_nonce) =
+            match parse_client_first(client_first) {
+                Some(parsed) => parsed,
+                None => {
+                    mosquitto_warn!(
+                        "SCRAM client {} sent an invalid or unsupported client-first message",
+                        client_id
+                    );
+                    return AuthOutcome::Deny;
+                }
+            };
+
+        let credentials = self.store.credentials(&username);
+        let (salt, iterations) = match &credentials {
+            Some(credentials) => (credentials.salt.clone(), credentials.iterations),
+            None => match self.unknown_user_salt(&username) {
+                Some(salt) => (salt, UNKNOWN_USER_ITERATIONS),
+                None => return AuthOutcome::Deny,
+            },
+        };
+        let nonce = match (self.nonce)() {
+            Some(server_nonce) => format!("{}{}", client_nonce, server_nonce),
+            None => return AuthOutcome::Deny,
+        };
+        let server_first = format!("r={},s={},i={}", nonce, BASE64.encode(salt), iterations);
+
+        self.conversations.insert(
+            client_id,
+            Conversation {
+                username,
+                gs2_header: gs2_header.to_string(),
+                client_first_bare: client_first_bare.to_string(),
+                server_first: server_first.clone(),
+                nonce,
+                credentials,
+            },
+        );
+        AuthOutcome::Continue(server_first.into_bytes())
+    }
+
+    /// Checks the proof of the client-final message, accepting with the server-final message.
+    /// Clients without a conversation and other auth methods are deferred.
+    pub fn on_auth_continue(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        method: Option<&str>,
+        data: Option<&[u8]>,
+    ) -> AuthOutcome {
+        if method != Some(METHOD) {
+            return AuthOutcome::Defer;
+        }
+        let client_id = match client.get_id() {
+            Some(client_id) => client_id,
+            None => return AuthOutcome::Deny,
+        };
+        let conversation = match self.conversations.remove(&client_id) {
+            Some(conversation) => conversation,
+            None => return AuthOutcome::Defer,
+        };
+
+        let client_final = data.and_then(|data| std::str::from_utf8(data).ok());
+        let (client_final_without_proof, proof) = match client_final
+            .and_then(|client_final| parse_client_final(client_final, &conversation))
+        {
+            Some(parsed) => parsed,
+            None => {
+                mosquitto_warn!(
+                    "SCRAM client {} sent an invalid client-final message",
+                    client_id
+                );
+                return AuthOutcome::Deny;
+            }
+        };
+
+        let auth_message = format!(
+            "{},{},{}",
+            conversation.client_first_bare, conversation.server_first, client_final_without_proof
+        );
+        let credentials = match conversation.credentials {
+            Some(credentials) if verify_proof(&credentials, &auth_message, &proof) => credentials,
+            _ => {
+                mosquitto_warn!(
+                    "SCRAM authentication of client {} as {} failed",
+                    client_id,
+                    conversation.username
+                );
+                return AuthOutcome::Deny;
+            }
+        };
+
+        if client.set_username(conversation.username).is_err() {
+            return AuthOutcome::Deny;
+        }
+        let server_signature = hmac(&credentials.server_key, auth_message.as_bytes());
+        let server_final = format!("v={}", BASE64.encode(server_signature));
+        AuthOutcome::Accept(Some(server_final.into_bytes()))
+    }
+
+    /// Drops the conversation of the client
+    pub fn on_disconnect(&mut self, client: &dyn MosquittoClientContext) {
+        if let Some(client_id) = client.get_id() {
+            self.conversations.remove(&client_id);
+        }
+    }
+
+    fn unknown_user_salt(&mut self, username: &str) -> Option<Vec<u8>> {
+        let secret = match self.secret {
+            Some(secret) => secret,
+            None => {
+                let mut secret = [0; 32];
+                random(&mut secret)?;
+                *self.secret.insert(secret)
+            }
+        };
+        Some(hmac(&secret, username.as_bytes())[..16].to_vec())
+    }
+}
+
+impl<S> fmt::Debug for Scram<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scram")
+            .field("conversations", &self.conversations.len())
+            .finish()
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn random(bytes: &mut [u8]) -> Option<()> {
+    match getrandom::getrandom(bytes) {
+        Ok(()) => Some(()),
+        Err(e) => {
+            mosquitto_warn!("SCRAM authentication denied, no random numbers: {}", e);
+            None
+        }
+    }
+}
+
+// 18 random bytes, base64 encoded, which has no commas
+fn random_nonce() -> Option<String> {
+    let mut nonce = [0; 18];
+    random(&mut nonce)?;
+    Some(BASE64.encode(nonce))
+}
+
+// Splits the client-first message into the GS2 header, the bare message, the username and the
+// nonce. Channel binding, authorization as another user and mandatory extensions aren't
+// supported.
+fn parse_client_first(client_first: &str) -> Option<(&str, &str, String, &str)> {
+    let (cbind_flag, rest) = client_first.split_once(',')?;
+    let (authzid, client_first_bare) = rest.split_once(',')?;
+    if cbind_flag != "n" && cbind_flag != "y" {
+        return None;
+    }
+    let gs2_header = &client_first[..cbind_flag.len() + authzid.len() + 2];
+
+    let mut attributes = client_first_bare.split(',');
+    let username = decode_saslname(attributes.next()?.strip_prefix("n=")?)?;
+    let nonce = attributes.next()?.strip_prefix("r=")?;
+    if username.is_empty() || !valid_nonce(nonce) {
+        return None;
+    }
+    if attributes.any(|attribute| attribute.starts_with("m=")) {
+        return None;
+    }
+    match authzid.strip_prefix("a=") {
+        Some(authzid) if decode_saslname(authzid)? != username => return None,
+        None if !authzid.is_empty() => return None,
+        _ => {}
+    }
+    Some((gs2_header, client_first_bare, username, nonce))
+}
+
+// Splits the client-final message into the message without the proof and the proof, checking
+// that it carries the GS2 header and nonce of the conversation
+fn parse_client_final<'a>(
+    client_final: &'a str,
+    conversation: &Conversation,
+) -> Option<(&'a str, Vec<u8>)> {
+    let (without_proof, proof) = client_final.rsplit_once(",p=")?;
+    let mut attributes = without_proof.split(',');
+    let channel_binding = BASE64.decode(attributes.next()?.strip_prefix("c=")?).ok()?;
+    let nonce = attributes.next()?.strip_prefix("r=")?;
+    if channel_binding != conversation.gs2_header.as_bytes() || nonce != conversation.nonce {
+        return None;
+    }
+    Some((without_proof, BASE64.decode(proof).ok()?))
+}
+
+// Recovers the client key from the proof and compares its hash to the stored key, in constant
+// time
+fn verify_proof(credentials: &Credentials, auth_message: &str, proof: &[u8]) -> bool {
+    if proof.len() != 32 {
+        return false;
+    }
+    let client_signature = hmac(&credentials.stored_key, auth_message.as_bytes());
+    let client_key: Vec<u8> = proof
+        .iter()
+        .zip(client_signature.iter())
+        .map(|(p, s)| p ^ s)
+        .collect();
+    let stored_key = Sha256::digest(client_key);
+    stored_key
+        .iter()
+        .zip(credentials.stored_key.iter())
+        .fold(0, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+// Usernames escape `,` as `=2C` and `=` as `=3D`
+fn decode_saslname(name: &str) -> Option<String> {
+    let mut decoded = String::with_capacity(name.len());
+    let mut parts = name.split('=');
+    decoded.push_str(parts.next()?);
+    for part in parts {
+        if let Some(rest) = part.strip_prefix("2C") {
+            decoded.push(',');
+            decoded.push_str(rest);
+        } else if let Some(rest) = part.strip_prefix("3D") {
+            decoded.push('=');
+            decoded.push_str(rest);
+        } else {
+            return None;
+        }
+    }
+    Some(decoded)
+}
+
+// Printable ASCII except the comma
+fn valid_nonce(nonce: &str) -> bool {
+    !nonce.is_empty()
+        && nonce
+            .bytes()
+            .all(|b| (0x21..=0x7e).contains(&b) && b != b',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeClient;
+
+    // RFC 7677, section 3
+    const CLIENT_FIRST: &[u8] = b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO";
+    const SERVER_FIRST: &[u8] =
+        b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    const CLIENT_FINAL: &[u8] = b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+        p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+    const SERVER_FINAL: &[u8] = b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+
+    fn scram() -> Scram<HashMap<String, Credentials>> {
+        let salt = BASE64.decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+        let mut users = HashMap::new();
+        users.insert(
+            "user".to_string(),
+            Credentials::derive(b"pencil", &salt, 4096),
+        );
+        let mut scram = Scram::new(users);
+        scram.nonce = || Some("%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0".to_string());
+        scram
+    }
+
+    fn start(
+        scram: &mut Scram<HashMap<String, Credentials>>,
+        client: &FakeClient,
+        data: &[u8],
+    ) -> AuthOutcome {
+        let auth = AuthStart {
+            method: Some(METHOD),
+            data: Some(data),
+            reauth: false,
+        };
+        scram.on_auth_start(client, auth)
+    }
+
+    #[test]
+    fn authenticates_the_rfc_7677_example() {
+        let mut scram = scram();
+        let client = FakeClient::new("client");
+
+        assert_eq!(
+            start(&mut scram, &client, CLIENT_FIRST),
+            AuthOutcome::Continue(SERVER_FIRST.to_vec())
+        );
+        assert_eq!(
+            scram.on_auth_continue(&client, Some(METHOD), Some(CLIENT_FINAL)),
+            AuthOutcome::Accept(Some(SERVER_FINAL.to_vec()))
+        );
+        assert_eq!(client.set_username_calls(), [Some("user".to_string())]);
+        assert_eq!(format!("{:?}", scram), "Scram { conversations: 0 }");
+    }
+
+    #[test]
+    fn denies_wrong_proofs_and_unknown_users() {
+        let mut scram = scram();
+        let client = FakeClient::new("client");
+
+        let mut wrong_proof = CLIENT_FINAL.to_vec();
+        let last = wrong_proof.len() - 5;
+        wrong_proof[last] = b'X';
+        start(&mut scram, &client, CLIENT_FIRST);
+        assert_eq!(
+            scram.on_auth_continue(&client, Some(METHOD), Some(&wrong_proof)),
+            AuthOutcome::Deny
+        );
+
+        // Unknown users get a salt that stays the same, like known ones
+        let unknown = start(&mut scram, &client, b"n,,n=nobody,r=rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(
+            start(&mut scram, &client, b"n,,n=nobody,r=rOprNGfwEbeRWgbNEkqO"),
+            unknown
+        );
+        let client_final = CLIENT_FINAL.to_vec();
+        assert_eq!(
+            scram.on_auth_continue(&client, Some(METHOD), Some(&client_final)),
+            AuthOutcome::Deny
+        );
+        assert!(client.set_username_calls().is_empty());
+    }
+
+    #[test]
+    fn checks_the_messages_of_the_conversation() {
+        let mut scram = scram();
+        let client = FakeClient::new("client");
+
+        // Channel binding and mandatory extensions aren't supported
+        for client_first in [
+            "p=tls-unique,,n=user,r=abc",
+            "n,a=other,n=user,r=abc",
+            "n,,n=user,r=abc,m=ext",
+            "n,,n=us=er,r=abc",
+            "n,,r=abc",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                start(&mut scram, &client, client_first.as_bytes()),
+                AuthOutcome::Deny,
+                "{}",
+                client_first
+            );
+        }
+        assert!(matches!(
+            start(&mut scram, &client, b"y,a=user,n=user,r=abc"),
+            AuthOutcome::Continue(_)
+        ));
+
+        // The final message has to repeat the GS2 header and the nonce
+        start(&mut scram, &client, CLIENT_FIRST);
+        let other_nonce =
+            b"c=biws,r=rOprNGfwEbeRWgbNEkqO,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=";
+        assert_eq!(
+            scram.on_auth_continue(&client, Some(METHOD), Some(other_nonce)),
+            AuthOutcome::Deny
+        );
+    }
+
+    #[test]
+    fn keeps_conversations_per_client_until_disconnect() {
+        let mut scram = scram();
+        let client = FakeClient::new("client");
+        let other = FakeClient::new("other");
+
+        let plain = AuthStart {
+            method: Some("PLAIN"),
+            data: None,
+            reauth: false,
+        };
+        assert_eq!(scram.on_auth_start(&client, plain), AuthOutcome::Defer);
+        assert_eq!(
+            scram.on_auth_continue(&client, Some(METHOD), Some(CLIENT_FINAL)),
+            AuthOutcome::Defer
+        );
+
+        start(&mut scram, &client, CLIENT_FIRST);
+        start(&mut scram, &other, CLIENT_FIRST);
+        scram.on_disconnect(&client);
+        assert_eq!(
+            scram.on_auth_continue(&client, Some(METHOD), Some(CLIENT_FINAL)),
+            AuthOutcome::Defer
+        );
+        assert_eq!(
+            scram.on_auth_continue(&other, Some(METHOD), Some(CLIENT_FINAL)),
+            AuthOutcome::Accept(Some(SERVER_FINAL.to_vec()))
+        );
+    }
+
+    #[test]
+    fn converts_credentials_from_and_to_rfc_5803() {
+        let credentials = Credentials::derive(b"pencil", b"salt", 4096);
+        let formatted = credentials.to_string();
+        assert!(formatted.starts_with("SCRAM-SHA-256$4096:c2FsdA==$"));
+        assert_eq!(Credentials::parse(&formatted), Some(credentials.clone()));
+        assert_eq!(format!("{:?}", credentials), "Credentials(4096 iterations)");
+
+        assert_eq!(
+            Credentials::parse("SCRAM-SHA-1$4096:c2FsdA==$AAAA:AAAA"),
+            None
+        );
+        assert_eq!(Credentials::parse(&formatted.replace("4096", "0")), None);
+        assert_eq!(Credentials::parse(&formatted[..formatted.len() - 4]), None);
+    }
+}
@@ -0,0 +1,634 @@
+// Bindings for the mosquitto 2.1.x plugin API.
+//
+// Pre-generated like the 2.0 bindings next to this file, which these extend with the events,
+// event structs and functions added in 2.1. The layouts of the events shared with 2.0 are
+// unchanged.
+
+pub const MOSQ_PLUGIN_VERSION: u32 = 5;
+pub const MOSQ_AUTH_PLUGIN_VERSION: u32 = 4;
+pub const MOSQ_ACL_NONE: u32 = 0;
+pub const MOSQ_ACL_READ: u32 = 1;
+pub const MOSQ_ACL_WRITE: u32 = 2;
+pub const MOSQ_ACL_SUBSCRIBE: u32 = 4;
+pub const MOSQ_ACL_UNSUBSCRIBE: u32 = 8;
+pub const MOSQ_LOG_NONE: u32 = 0;
+pub const MOSQ_LOG_INFO: u32 = 1;
+pub const MOSQ_LOG_NOTICE: u32 = 2;
+pub const MOSQ_LOG_WARNING: u32 = 4;
+pub const MOSQ_LOG_ERR: u32 = 8;
+pub const MOSQ_LOG_DEBUG: u32 = 16;
+pub const MOSQ_LOG_SUBSCRIBE: u32 = 32;
+pub const MOSQ_LOG_UNSUBSCRIBE: u32 = 64;
+pub const MOSQ_LOG_WEBSOCKETS: u32 = 128;
+pub const MOSQ_LOG_INTERNAL: u32 = 2147483648;
+pub const MOSQ_LOG_ALL: u32 = 4294967295;
+pub const MOSQ_MQTT_ID_MAX_LENGTH: u32 = 23;
+pub const MOSQ_OPT_PROTOCOL_VERSION: u32 = 1;
+pub const MOSQ_OPT_SSL_CTX: u32 = 2;
+pub const MOSQ_OPT_SSL_CTX_WITH_DEFAULTS: u32 = 3;
+pub const MOSQ_OPT_RECEIVE_MAXIMUM: u32 = 4;
+pub const MOSQ_OPT_SEND_MAXIMUM: u32 = 5;
+pub const MOSQ_OPT_TLS_KEYFORM: u32 = 6;
+pub const MOSQ_OPT_TLS_ENGINE: u32 = 7;
+pub const MOSQ_OPT_TLS_ENGINE_KPASS_SHA1: u32 = 8;
+pub const MOSQ_OPT_TLS_OCSP_REQUIRED: u32 = 9;
+pub const MOSQ_OPT_TLS_ALPN: u32 = 10;
+pub const MOSQ_OPT_TCP_NODELAY: u32 = 11;
+pub const MOSQ_OPT_BIND_ADDRESS: u32 = 12;
+pub const MOSQ_OPT_TLS_USE_OS_CERTS: u32 = 13;
+pub type __time_t = ::std::os::raw::c_long;
+pub type time_t = __time_t;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto {
+    _unused: [u8; 0],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_message {
+    pub mid: ::std::os::raw::c_int,
+    pub topic: *mut ::std::os::raw::c_char,
+    pub payload: *mut ::std::os::raw::c_void,
+    pub payloadlen: ::std::os::raw::c_int,
+    pub qos: ::std::os::raw::c_int,
+    pub retain: bool,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mqtt5__property {
+    _unused: [u8; 0],
+}
+pub type mosquitto_property = mqtt5__property;
+extern "C" {
+    pub fn mosquitto_lib_version(
+        major: *mut ::std::os::raw::c_int,
+        minor: *mut ::std::os::raw::c_int,
+        revision: *mut ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_strerror(mosq_errno: ::std::os::raw::c_int) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_connack_string(
+        connack_code: ::std::os::raw::c_int,
+    ) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_reason_string(
+        reason_code: ::std::os::raw::c_int,
+    ) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_string_to_command(
+        str_: *const ::std::os::raw::c_char,
+        cmd: *mut ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_sub_topic_tokenise(
+        subtopic: *const ::std::os::raw::c_char,
+        topics: *mut *mut *mut ::std::os::raw::c_char,
+        count: *mut ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_sub_topic_tokens_free(
+        topics: *mut *mut *mut ::std::os::raw::c_char,
+        count: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_topic_matches_sub(
+        sub: *const ::std::os::raw::c_char,
+        topic: *const ::std::os::raw::c_char,
+        result: *mut bool,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_topic_matches_sub2(
+        sub: *const ::std::os::raw::c_char,
+        sublen: usize,
+        topic: *const ::std::os::raw::c_char,
+        topiclen: usize,
+        result: *mut bool,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_pub_topic_check(topic: *const ::std::os::raw::c_char) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_pub_topic_check2(
+        topic: *const ::std::os::raw::c_char,
+        topiclen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_sub_topic_check(topic: *const ::std::os::raw::c_char) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_sub_topic_check2(
+        topic: *const ::std::os::raw::c_char,
+        topiclen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_validate_utf8(
+        str_: *const ::std::os::raw::c_char,
+        len: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_add_byte(
+        proplist: *mut *mut mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: u8,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_add_int16(
+        proplist: *mut *mut mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: u16,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_add_int32(
+        proplist: *mut *mut mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: u32,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_add_varint(
+        proplist: *mut *mut mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: u32,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_add_binary(
+        proplist: *mut *mut mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *const ::std::os::raw::c_void,
+        len: u16,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_add_string(
+        proplist: *mut *mut mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_add_string_pair(
+        proplist: *mut *mut mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        name: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_identifier(
+        property: *const mosquitto_property,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_next(
+        proplist: *const mosquitto_property,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_read_byte(
+        proplist: *const mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *mut u8,
+        skip_first: bool,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_read_int16(
+        proplist: *const mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *mut u16,
+        skip_first: bool,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_read_int32(
+        proplist: *const mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *mut u32,
+        skip_first: bool,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_read_varint(
+        proplist: *const mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *mut u32,
+        skip_first: bool,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_read_binary(
+        proplist: *const mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *mut *mut ::std::os::raw::c_void,
+        len: *mut u16,
+        skip_first: bool,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_read_string(
+        proplist: *const mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        value: *mut *mut ::std::os::raw::c_char,
+        skip_first: bool,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_read_string_pair(
+        proplist: *const mosquitto_property,
+        identifier: ::std::os::raw::c_int,
+        name: *mut *mut ::std::os::raw::c_char,
+        value: *mut *mut ::std::os::raw::c_char,
+        skip_first: bool,
+    ) -> *const mosquitto_property;
+}
+extern "C" {
+    pub fn mosquitto_property_free_all(properties: *mut *mut mosquitto_property);
+}
+extern "C" {
+    pub fn mosquitto_property_copy_all(
+        dest: *mut *mut mosquitto_property,
+        src: *const mosquitto_property,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_check_command(
+        command: ::std::os::raw::c_int,
+        identifier: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_check_all(
+        command: ::std::os::raw::c_int,
+        properties: *const mosquitto_property,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_property_identifier_to_string(
+        identifier: ::std::os::raw::c_int,
+    ) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_string_to_property_info(
+        propname: *const ::std::os::raw::c_char,
+        identifier: *mut ::std::os::raw::c_int,
+        type_: *mut ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_plugin_id_t {
+    _unused: [u8; 0],
+}
+pub const mosquitto_protocol_mp_mqtt: mosquitto_protocol = 0;
+pub const mosquitto_protocol_mp_mqttsn: mosquitto_protocol = 1;
+pub const mosquitto_protocol_mp_websockets: mosquitto_protocol = 2;
+pub type mosquitto_protocol = ::std::os::raw::c_uint;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_opt {
+    pub key: *mut ::std::os::raw::c_char,
+    pub value: *mut ::std::os::raw::c_char,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_auth_opt {
+    pub key: *mut ::std::os::raw::c_char,
+    pub value: *mut ::std::os::raw::c_char,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_acl_msg {
+    pub topic: *const ::std::os::raw::c_char,
+    pub payload: *const ::std::os::raw::c_void,
+    pub payloadlen: ::std::os::raw::c_long,
+    pub qos: ::std::os::raw::c_int,
+    pub retain: bool,
+}
+pub const mosquitto_plugin_event_MOSQ_EVT_RELOAD: mosquitto_plugin_event = 1;
+pub const mosquitto_plugin_event_MOSQ_EVT_ACL_CHECK: mosquitto_plugin_event = 2;
+pub const mosquitto_plugin_event_MOSQ_EVT_BASIC_AUTH: mosquitto_plugin_event = 3;
+pub const mosquitto_plugin_event_MOSQ_EVT_EXT_AUTH_START: mosquitto_plugin_event = 4;
+pub const mosquitto_plugin_event_MOSQ_EVT_EXT_AUTH_CONTINUE: mosquitto_plugin_event = 5;
+pub const mosquitto_plugin_event_MOSQ_EVT_CONTROL: mosquitto_plugin_event = 6;
+pub const mosquitto_plugin_event_MOSQ_EVT_MESSAGE: mosquitto_plugin_event = 7;
+pub const mosquitto_plugin_event_MOSQ_EVT_PSK_KEY: mosquitto_plugin_event = 8;
+pub const mosquitto_plugin_event_MOSQ_EVT_TICK: mosquitto_plugin_event = 9;
+pub const mosquitto_plugin_event_MOSQ_EVT_DISCONNECT: mosquitto_plugin_event = 10;
+pub const mosquitto_plugin_event_MOSQ_EVT_CONNECT: mosquitto_plugin_event = 11;
+pub const mosquitto_plugin_event_MOSQ_EVT_SUBSCRIBE: mosquitto_plugin_event = 12;
+pub const mosquitto_plugin_event_MOSQ_EVT_UNSUBSCRIBE: mosquitto_plugin_event = 13;
+pub const mosquitto_plugin_event_MOSQ_EVT_CLIENT_OFFLINE: mosquitto_plugin_event = 28;
+pub type mosquitto_plugin_event = ::std::os::raw::c_uint;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_reload {
+    pub future: *mut ::std::os::raw::c_void,
+    pub options: *mut mosquitto_opt,
+    pub option_count: ::std::os::raw::c_int,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_acl_check {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub topic: *const ::std::os::raw::c_char,
+    pub payload: *const ::std::os::raw::c_void,
+    pub properties: *const mosquitto_property,
+    pub access: ::std::os::raw::c_int,
+    pub payloadlen: u32,
+    pub qos: u8,
+    pub retain: bool,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_basic_auth {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub username: *mut ::std::os::raw::c_char,
+    pub password: *mut ::std::os::raw::c_char,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_psk_key {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub hint: *const ::std::os::raw::c_char,
+    pub identity: *const ::std::os::raw::c_char,
+    pub key: *mut ::std::os::raw::c_char,
+    pub max_key_len: ::std::os::raw::c_int,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_extended_auth {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub data_in: *const ::std::os::raw::c_void,
+    pub data_out: *mut ::std::os::raw::c_void,
+    pub data_in_len: u16,
+    pub data_out_len: u16,
+    pub auth_method: *const ::std::os::raw::c_char,
+    pub future2: [*mut ::std::os::raw::c_void; 3usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_control {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub topic: *const ::std::os::raw::c_char,
+    pub payload: *const ::std::os::raw::c_void,
+    pub properties: *const mosquitto_property,
+    pub reason_string: *mut ::std::os::raw::c_char,
+    pub payloadlen: u32,
+    pub qos: u8,
+    pub reason_code: u8,
+    pub retain: bool,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_message {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub topic: *mut ::std::os::raw::c_char,
+    pub payload: *mut ::std::os::raw::c_void,
+    pub properties: *mut mosquitto_property,
+    pub reason_string: *mut ::std::os::raw::c_char,
+    pub payloadlen: u32,
+    pub qos: u8,
+    pub reason_code: u8,
+    pub retain: bool,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_tick {
+    pub future: *mut ::std::os::raw::c_void,
+    pub now_ns: ::std::os::raw::c_long,
+    pub next_ns: ::std::os::raw::c_long,
+    pub now_s: time_t,
+    pub next_s: time_t,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_disconnect {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub reason: ::std::os::raw::c_int,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_subscription {
+    pub clientid: *mut ::std::os::raw::c_char,
+    pub topic_filter: *mut ::std::os::raw::c_char,
+    pub properties: *mut mosquitto_property,
+    pub identifier: u32,
+    pub options: u8,
+    pub padding: [u8; 3usize],
+    pub future2: [*mut ::std::os::raw::c_void; 8usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_connect {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub future2: [*mut ::std::os::raw::c_void; 10usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_subscribe {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub data: mosquitto_subscription,
+    pub future2: [*mut ::std::os::raw::c_void; 8usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_unsubscribe {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub data: mosquitto_subscription,
+    pub future2: [*mut ::std::os::raw::c_void; 8usize],
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct mosquitto_evt_client_offline {
+    pub future: *mut ::std::os::raw::c_void,
+    pub client: *mut mosquitto,
+    pub reason: ::std::os::raw::c_int,
+    pub future2: [*mut ::std::os::raw::c_void; 4usize],
+}
+pub type MOSQ_FUNC_generic_callback = ::std::option::Option<
+    unsafe extern "C" fn(
+        arg1: ::std::os::raw::c_int,
+        arg2: *mut ::std::os::raw::c_void,
+        arg3: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int,
+>;
+extern "C" {
+    pub fn mosquitto_callback_register(
+        identifier: *mut mosquitto_plugin_id_t,
+        event: ::std::os::raw::c_int,
+        cb_func: MOSQ_FUNC_generic_callback,
+        event_data: *const ::std::os::raw::c_void,
+        userdata: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_callback_unregister(
+        identifier: *mut mosquitto_plugin_id_t,
+        event: ::std::os::raw::c_int,
+        cb_func: MOSQ_FUNC_generic_callback,
+        event_data: *const ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_calloc(nmemb: usize, size: usize) -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn mosquitto_free(mem: *mut ::std::os::raw::c_void);
+}
+extern "C" {
+    pub fn mosquitto_malloc(size: usize) -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn mosquitto_realloc(
+        ptr: *mut ::std::os::raw::c_void,
+        size: usize,
+    ) -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn mosquitto_strdup(s: *const ::std::os::raw::c_char) -> *mut ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_log_printf(
+        level: ::std::os::raw::c_int,
+        fmt: *const ::std::os::raw::c_char,
+        ...
+    );
+}
+extern "C" {
+    pub fn mosquitto_client_address(client: *const mosquitto) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_client_clean_session(client: *const mosquitto) -> bool;
+}
+extern "C" {
+    pub fn mosquitto_client_id(client: *const mosquitto) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_client_keepalive(client: *const mosquitto) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_client_certificate(client: *const mosquitto) -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn mosquitto_client_protocol(client: *const mosquitto) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_client_protocol_version(client: *const mosquitto) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_client_sub_count(client: *const mosquitto) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_client_port(client: *const mosquitto) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_client_username(client: *const mosquitto) -> *const ::std::os::raw::c_char;
+}
+extern "C" {
+    pub fn mosquitto_set_username(
+        client: *mut mosquitto,
+        username: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_kick_client_by_clientid(
+        clientid: *const ::std::os::raw::c_char,
+        with_will: bool,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_kick_client_by_username(
+        username: *const ::std::os::raw::c_char,
+        with_will: bool,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_broker_publish(
+        clientid: *const ::std::os::raw::c_char,
+        topic: *const ::std::os::raw::c_char,
+        payloadlen: ::std::os::raw::c_int,
+        payload: *mut ::std::os::raw::c_void,
+        qos: ::std::os::raw::c_int,
+        retain: bool,
+        properties: *mut mosquitto_property,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_broker_publish_copy(
+        clientid: *const ::std::os::raw::c_char,
+        topic: *const ::std::os::raw::c_char,
+        payloadlen: ::std::os::raw::c_int,
+        payload: *const ::std::os::raw::c_void,
+        qos: ::std::os::raw::c_int,
+        retain: bool,
+        properties: *mut mosquitto_property,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_complete_basic_auth(
+        clientid: *const ::std::os::raw::c_char,
+        result: ::std::os::raw::c_int,
+    );
+}
+extern "C" {
+    pub fn mosquitto_plugin_set_info(
+        identifier: *mut mosquitto_plugin_id_t,
+        plugin_name: *const ::std::os::raw::c_char,
+        plugin_version: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_plugin_version(
+        supported_version_count: ::std::os::raw::c_int,
+        supported_versions: *const ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_plugin_init(
+        identifier: *mut mosquitto_plugin_id_t,
+        userdata: *mut *mut ::std::os::raw::c_void,
+        options: *mut mosquitto_opt,
+        option_count: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn mosquitto_plugin_cleanup(
+        userdata: *mut ::std::os::raw::c_void,
+        options: *mut mosquitto_opt,
+        option_count: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
@@ -0,0 +1,273 @@
+// The certificate a client presented on a TLS listener.
+//
+// mosquitto_client_certificate hands out a reference counted OpenSSL X509, which is encoded to
+// DER with i2d_X509 and released right away. Both come from the libcrypto the broker is linked
+// against, so the plugin doesn't link OpenSSL itself. They are looked up when first needed
+// instead of linked, a broker built without TLS has no libcrypto, and the plugin has to load
+// into it all the same.
+
+use crate::mosquitto_dev::mosquitto;
+use std::os::raw::{c_int, c_uchar, c_void};
+use std::ptr::null_mut;
+use std::sync::OnceLock;
+
+#[doc(hidden)]
+pub type I2dX509 = unsafe extern "C" fn(x509: *mut c_void, out: *mut *mut c_uchar) -> c_int;
+#[doc(hidden)]
+pub type X509Free = unsafe extern "C" fn(x509: *mut c_void);
+
+#[derive(Clone, Copy)]
+struct Libcrypto {
+    i2d_x509: I2dX509,
+    x509_free: X509Free,
+}
+
+static LIBCRYPTO: OnceLock<Option<Libcrypto>> = OnceLock::new();
+
+// The functions of the libcrypto the broker loaded, None without one
+fn libcrypto() -> Option<Libcrypto> {
+    *LIBCRYPTO.get_or_init(lookup_libcrypto)
+}
+
+#[cfg(unix)]
+fn lookup_libcrypto() -> Option<Libcrypto> {
+    unsafe {
+        let i2d_x509 = libc::dlsym(libc::RTLD_DEFAULT, b"i2d_X509\0".as_ptr() as _);
+        let x509_free = libc::dlsym(libc::RTLD_DEFAULT, b"X509_free\0".as_ptr() as _);
+        if i2d_x509.is_null() || x509_free.is_null() {
+            return None;
+        }
+        Some(Libcrypto {
+            i2d_x509: std::mem::transmute::<*mut c_void, I2dX509>(i2d_x509),
+            x509_free: std::mem::transmute::<*mut c_void, X509Free>(x509_free),
+        })
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_libcrypto() -> Option<Libcrypto> {
+    None
+}
+
+// Used by the mock broker of mosquitto-plugin-testing in place of the lookup, which can't find
+// functions of the test binary. Has no effect once a certificate was read.
+#[doc(hidden)]
+pub fn __use_libcrypto(i2d_x509: I2dX509, x509_free: X509Free) {
+    let _ = LIBCRYPTO.set(Some(Libcrypto {
+        i2d_x509,
+        x509_free,
+    }));
+}
+
+/// A DER encoded X.509 certificate, owned by the plugin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    der: Vec<u8>,
+}
+
+impl Certificate {
+    pub fn from_der(der: Vec<u8>) -> Certificate {
+        Certificate { der }
+    }
+
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    pub fn into_der(self) -> Vec<u8> {
+        self.der
+    }
+
+    // Copies the certificate of the client out of the broker, None for clients without one and
+    // for brokers without TLS
+    pub(crate) fn from_client(client: *mut mosquitto) -> Option<Certificate> {
+        let Libcrypto {
+            i2d_x509,
+            x509_free,
+        } = libcrypto()?;
+        unsafe {
+            let x509 = crate::mosquitto_dev::mosquitto_client_certificate(client);
+            if x509.is_null() {
+                return None;
+            }
+            // The first call only tells the length, the second writes the encoding and moves the
+            // pointer past it
+            let len = i2d_x509(x509, null_mut());
+            let der = if len > 0 {
+                let mut der = vec![0u8; len as usize];
+                let mut out = der.as_mut_ptr();
+                let written = i2d_x509(x509, &mut out);
+                (written == len).then_some(der)
+            } else {
+                None
+            };
+            x509_free(x509);
+            der.map(Certificate::from_der)
+        }
+    }
+
+    /// Parses the certificate, None if it isn't valid DER encoded X.509
+    #[cfg(feature = "x509-parser")]
+    pub fn parse(&self) -> Option<CertificateInfo> {
+        use x509_parser::extensions::GeneralName;
+
+        let (_, cert) = x509_parser::parse_x509_certificate(&self.der).ok()?;
+        let subject_alt_names = match cert.subject_alternative_name() {
+            Ok(Some(extension)) => extension
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(SubjectAltName::Dns(name.to_string())),
+                    GeneralName::RFC822Name(name) => Some(SubjectAltName::Email(name.to_string())),
+                    GeneralName::URI(uri) => Some(SubjectAltName::Uri(uri.to_string())),
+                    GeneralName::IPAddress(ip) => ip_address(ip).map(SubjectAltName::Ip),
+                    _ => None,
+                })
+                .collect(),
+            Ok(None) => Vec::new(),
+            Err(_) => return None,
+        };
+        let subject_common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(String::from);
+        Some(CertificateInfo {
+            subject: cert.subject().to_string(),
+            subject_common_name,
+            subject_alt_names,
+            issuer: cert.issuer().to_string(),
+            serial: cert.raw_serial().to_vec(),
+            not_before: system_time(cert.validity().not_before.timestamp()),
+            not_after: system_time(cert.validity().not_after.timestamp()),
+        })
+    }
+}
+
+/// The fields of a certificate a plugin usually maps identities with
+#[cfg(feature = "x509-parser")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    /// The subject distinguished name, like "CN=sensor-1, O=tenant"
+    pub subject: String,
+    pub subject_common_name: Option<String>,
+    /// DNS names, emails, URIs and IP addresses of the subject alternative name extension
+    pub subject_alt_names: Vec<SubjectAltName>,
+    /// The issuer distinguished name
+    pub issuer: String,
+    /// The serial number, big endian
+    pub serial: Vec<u8>,
+    pub not_before: std::time::SystemTime,
+    pub not_after: std::time::SystemTime,
+}
+
+#[cfg(feature = "x509-parser")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Email(String),
+    Uri(String),
+    Ip(std::net::IpAddr),
+}
+
+#[cfg(feature = "x509-parser")]
+fn ip_address(bytes: &[u8]) -> Option<std::net::IpAddr> {
+    use std::convert::TryFrom;
+
+    match bytes.len() {
+        4 => Some(<[u8; 4]>::try_from(bytes).ok()?.into()),
+        16 => Some(<[u8; 16]>::try_from(bytes).ok()?.into()),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "x509-parser")]
+fn system_time(timestamp: i64) -> std::time::SystemTime {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    if timestamp >= 0 {
+        UNIX_EPOCH + Duration::from_secs(timestamp as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(timestamp.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeClient, MockBroker};
+    use crate::{MosquittoClient, MosquittoClientContext};
+
+    fn certificate() -> Vec<u8> {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params =
+            rcgen::CertificateParams::new(vec!["sensor.example.com".to_string()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "sensor-1");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "tenant");
+        params
+            .subject_alt_names
+            .push(rcgen::SanType::IpAddress("10.0.0.7".parse().unwrap()));
+        params.serial_number = Some(rcgen::SerialNumber::from(vec![0x01, 0x02, 0x03]));
+        params.not_before = rcgen::date_time_ymd(2024, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2030, 1, 1);
+        params.self_signed(&key).unwrap().der().to_vec()
+    }
+
+    #[test]
+    fn copies_the_certificate_out_of_the_broker() {
+        let broker = MockBroker::new();
+        let der = certificate();
+        let fake = FakeClient::new("client").certificate(der.clone());
+        let client = MosquittoClient {
+            client: fake.as_ptr(),
+        };
+
+        assert_eq!(
+            client.get_certificate().map(Certificate::into_der),
+            Some(der)
+        );
+        assert_eq!(broker.certificates_in_use(), 0);
+
+        let without = FakeClient::new("client");
+        let client = MosquittoClient {
+            client: without.as_ptr(),
+        };
+        assert_eq!(client.get_certificate(), None);
+    }
+
+    #[cfg(feature = "x509-parser")]
+    #[test]
+    fn parses_identity_fields() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let info = Certificate::from_der(certificate()).parse().unwrap();
+        assert_eq!(info.subject, "CN=sensor-1, O=tenant");
+        assert_eq!(info.subject_common_name.as_deref(), Some("sensor-1"));
+        assert_eq!(info.issuer, info.subject);
+        assert_eq!(
+            info.subject_alt_names,
+            vec![
+                SubjectAltName::Dns("sensor.example.com".to_string()),
+                SubjectAltName::Ip("10.0.0.7".parse().unwrap()),
+            ]
+        );
+        assert_eq!(info.serial, vec![0x01, 0x02, 0x03]);
+        assert_eq!(
+            info.not_before,
+            UNIX_EPOCH + Duration::from_secs(1_704_067_200)
+        );
+        assert_eq!(
+            info.not_after,
+            UNIX_EPOCH + Duration::from_secs(1_893_456_000)
+        );
+
+        assert_eq!(Certificate::from_der(vec![0x30, 0x00]).parse(), None);
+    }
+}
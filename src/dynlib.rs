@@ -7,12 +7,19 @@
 #[macro_export]
 macro_rules! create_dynamic_library {
     ($t:ty) => {
+        // None for strings that aren't valid UTF-8, which is logged with the message given
         macro_rules! debug_assert_null_or_str {
             ($ptr:expr, $l:literal) => {
                 unsafe {
                     debug_assert!(!$ptr.is_null(), "ptr is null!");
                     let c_str = std::ffi::CStr::from_ptr($ptr);
-                    c_str.to_str().expect($l)
+                    match c_str.to_str() {
+                        Ok(s) => Some(s),
+                        Err(_) => {
+                            mosquitto_warn!($l);
+                            None
+                        }
+                    }
                 }
             };
         }
@@ -31,8 +38,9 @@ macro_rules! create_dynamic_library {
             };
         }
 
+        // Already in scope when the caller glob imports the crate
+        #[allow(unused_imports)]
         use mosquitto_dev::*;
-        use std::ffi::CString;
         use std::os::raw::c_int;
         use std::os::raw::c_void;
 
@@ -47,523 +55,781 @@ macro_rules! create_dynamic_library {
         /// Structure internal to the plugin binder.
         /// identifier is the plugin identifier recievied in mosquitto_plugin_init
         /// external_user_data is the struct defined by the library user.
+        /// registrations are the callbacks registered for it, unregistered at cleanup.
+        /// authenticated are the clients that completed extended auth, to tell re-authentication.
         struct InternalUserData {
             identifier: *mut c_void,
             external_user_data: $t,
+            registrations: __Registrations,
+            authenticated: std::collections::HashSet<usize>,
         }
 
         #[no_mangle]
-        pub extern "C" fn mosquitto_plugin_version() -> isize {
-            mosquitto_dev::MOSQ_PLUGIN_VERSION as isize
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn mosquitto_plugin_version(
+            supported_version_count: c_int,
+            supported_versions: *const c_int,
+        ) -> c_int {
+            __plugin_version(supported_version_count, supported_versions)
         }
 
         // Trampoline functions that are used as callback for the mosquitto_callback_register
         // These function satisfy the types of the C bindings and then call their corresponding safer rust calls.
+        // They are only handed to mosquitto by pointer, every registration passes the
+        // InternalUserData of its own plugin instance as user data.
 
-        #[no_mangle]
         extern "C" fn on_reload_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on_reload_trampoline event_data is null"
-            );
-            debug_assert!(
-                !user_data.is_null(),
-                "on_reload_trampoline user_data is null"
-            );
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_reload =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_reload) };
-            let opts = __from_ptr_and_size(event_data.options, event_data.option_count as _);
-            user_data.external_user_data.on_reload(opts);
-            0
+            __catch_panic(<$t>::PANIC_POLICY, "on_reload", Error::Unknown, Success.into(), || {
+                debug_assert!(
+                    !event_data.is_null(),
+                    "on_reload_trampoline event_data is null"
+                );
+                debug_assert!(
+                    !user_data.is_null(),
+                    "on_reload_trampoline user_data is null"
+                );
+                let user_data: &mut InternalUserData =
+                    unsafe { &mut *(user_data as *mut InternalUserData) };
+                let event_data: &mut mosquitto_evt_reload =
+                    unsafe { &mut *(event_data as *mut mosquitto_evt_reload) };
+                let opts =
+                    match __from_ptr_and_size(event_data.options, event_data.option_count as _) {
+                        Ok(opts) => opts,
+                        Err(e) => return __rebuild_failed(e),
+                    };
+                match <$t>::RELOAD_POLICY {
+                    ReloadPolicy::InPlace => {
+                        user_data.external_user_data.on_reload(opts);
+                        0
+                    }
+                    // The running instance is only replaced once its successor is complete
+                    ReloadPolicy::Rebuild => match user_data.external_user_data.rebuild(opts) {
+                        Ok(instance) => {
+                            user_data.external_user_data = instance;
+                            0
+                        }
+                        Err(e) => __rebuild_failed(e),
+                    },
+                }
+            })
         }
 
-        #[no_mangle]
         extern "C" fn on_acl_check_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on_acl_check_trampoline event_data is null"
-            );
-            debug_assert!(
-                !user_data.is_null(),
-                "on_acl_check_trampoline user_data is null"
-            );
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_acl_check =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_acl_check) };
-            let access_level: AccessLevel = event_data.access.into();
-            let access_level = if let Some(level) = access_level.into() {
-                level
-            } else {
-                mosquitto_warn!("Unexpected access level for acl check. {:?}", access_level);
-                return Error::Unknown.into();
-            };
-
-            let topic: &str = debug_assert_null_or_str!(
-                event_data.topic,
-                "failed to create topic str on acl check trampoline"
-            );
-
-            let payload: &[u8] = debug_assert_null_or_slice!(
-                event_data.payload,
-                event_data.payloadlen,
-                "acl_check_trampoline payload is null"
-            );
-
-            let msg = MosquittoMessage {
-                topic,
-                payload,
-                qos: event_data.qos.into(),
-                retain: event_data.retain,
-            };
-            match user_data.external_user_data.acl_check(
-                &MosquittoClient {
-                    client: event_data.client,
+            __catch_panic(
+                <$t>::PANIC_POLICY,
+                "acl_check",
+                Error::AclDenied,
+                Error::PluginDefer.into(),
+                || {
+                    debug_assert!(
+                        !event_data.is_null(),
+                        "on_acl_check_trampoline event_data is null"
+                    );
+                    debug_assert!(
+                        !user_data.is_null(),
+                        "on_acl_check_trampoline user_data is null"
+                    );
+                    let user_data: &mut InternalUserData =
+                        unsafe { &mut *(user_data as *mut InternalUserData) };
+                    let event_data: &mut mosquitto_evt_acl_check =
+                        unsafe { &mut *(event_data as *mut mosquitto_evt_acl_check) };
+                    let access_level: AccessLevel = event_data.access.into();
+                    let access_level = if let Some(level) = access_level.into() {
+                        level
+                    } else {
+                        mosquitto_warn!(
+                            "Unexpected access level for acl check. {:?}",
+                            access_level
+                        );
+                        return Error::Unknown.into();
+                    };
+
+                    let topic: &str = match debug_assert_null_or_str!(
+                        event_data.topic,
+                        "acl check denied, the topic is not valid UTF-8"
+                    ) {
+                        Some(topic) => topic,
+                        None => return Error::AclDenied.into(),
+                    };
+
+                    let payload: &[u8] = debug_assert_null_or_slice!(
+                        event_data.payload,
+                        event_data.payloadlen,
+                        "acl_check_trampoline payload is null"
+                    );
+
+                    let msg = MosquittoMessage {
+                        topic,
+                        payload,
+                        qos: event_data.qos.into(),
+                        retain: event_data.retain,
+                        properties: unsafe { Properties::from_ptr(event_data.properties) },
+                    };
+                    let client = MosquittoClient {
+                        client: event_data.client,
+                    };
+                    match user_data.external_user_data.acl_check(
+                        &client,
+                        client.get_listener(),
+                        access_level,
+                        msg,
+                    ) {
+                        Ok(s) => s.into(),
+                        Err(e) => e.into(),
+                    }
                 },
-                access_level,
-                msg,
-            ) {
-                Ok(s) => s.into(),
-                Err(e) => e.into(),
-            }
+            )
         }
 
-        #[no_mangle]
         extern "C" fn on_basic_auth_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on_basic_auth_trampoline event_data is null"
-            );
-            debug_assert!(
-                !user_data.is_null(),
-                "on_basic_auth_trampoline user_data is null"
-            );
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_basic_auth =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_basic_auth) };
-
-            let username: Option<&str> = unsafe {
-                if event_data.username.is_null() {
-                    None
-                } else {
-                    let c_str = std::ffi::CStr::from_ptr(event_data.username);
-                    Some(c_str.to_str().expect(
-                        "basic auth trampoline failed to create username &str from CStr pointer",
-                    ))
-                }
-            };
-            let password: Option<&str> = unsafe {
-                if event_data.password.is_null() {
-                    None
-                } else {
-                    let c_str = std::ffi::CStr::from_ptr(event_data.password);
-                    Some(c_str.to_str().expect(
-                        "basic auth trampoline failed to create password &str from CStr pointer",
-                    ))
-                }
-            };
-
-            debug_assert!(
-                !event_data.client.is_null(),
-                "no client in basic auth trampoline"
-            );
-
-            match user_data.external_user_data.username_password(
-                &MosquittoClient {
-                    client: event_data.client,
+            __catch_panic(
+                <$t>::PANIC_POLICY,
+                "username_password",
+                Error::Auth,
+                Error::PluginDefer.into(),
+                || {
+                    debug_assert!(
+                        !event_data.is_null(),
+                        "on_basic_auth_trampoline event_data is null"
+                    );
+                    debug_assert!(
+                        !user_data.is_null(),
+                        "on_basic_auth_trampoline user_data is null"
+                    );
+                    let user_data: &mut InternalUserData =
+                        unsafe { &mut *(user_data as *mut InternalUserData) };
+                    let event_data: &mut mosquitto_evt_basic_auth =
+                        unsafe { &mut *(event_data as *mut mosquitto_evt_basic_auth) };
+
+                    // Neither is necessarily UTF-8, the plugin decides what it accepts
+                    let username: Option<&[u8]> =
+                        (!event_data.username.is_null()).then(|| unsafe {
+                            std::ffi::CStr::from_ptr(event_data.username).to_bytes()
+                        });
+                    let password: Option<&[u8]> =
+                        (!event_data.password.is_null()).then(|| unsafe {
+                            std::ffi::CStr::from_ptr(event_data.password).to_bytes()
+                        });
+
+                    debug_assert!(
+                        !event_data.client.is_null(),
+                        "no client in basic auth trampoline"
+                    );
+
+                    let client = MosquittoClient {
+                        client: event_data.client,
+                    };
+                    match user_data.external_user_data.username_password(
+                        &client,
+                        client.get_listener(),
+                        username,
+                        password,
+                    ) {
+                        Ok(r) => r.into(),
+                        Err(e) => e.into(),
+                    }
                 },
-                username,
-                password,
-            ) {
-                Ok(r) => r.into(),
-                Err(e) => e.into(),
-            }
+            )
         }
 
-        #[no_mangle]
         extern "C" fn on_auth(
             event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(!event_data.is_null(), "on_auth event_data is null");
-            debug_assert!(!user_data.is_null(), "on_auth user_data is null");
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_extended_auth =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_extended_auth) };
-
-            let method = (!event_data.auth_method.is_null()).then(|| unsafe {
-                let c_str = std::ffi::CStr::from_ptr(event_data.auth_method);
-                c_str.to_str().expect(
-                    "auth start trampoline failed to create auth method &str from CStr pointer",
-                )
-            });
-
-            let data_in = (!event_data.data_in.is_null()).then(|| unsafe {
-                std::slice::from_raw_parts(
-                    event_data.data_in as *const u8,
-                    event_data.data_in_len as usize,
-                )
-            });
-
-            let result = if event == MosquittoPluginEvent::MosqEvtExtAuthStart as c_int {
-                user_data.external_user_data.on_auth_start(
-                    &MosquittoClient {
-                        client: event_data.client,
-                    },
-                    method,
-                    data_in,
-                )
-            } else if event == MosquittoPluginEvent::MosqEvtExtAuthContinue as c_int {
-                user_data.external_user_data.on_auth_continue(
-                    &MosquittoClient {
+            __catch_panic(
+                <$t>::PANIC_POLICY,
+                "on_auth",
+                Error::Auth,
+                Error::PluginDefer.into(),
+                || {
+                    debug_assert!(!event_data.is_null(), "on_auth event_data is null");
+                    debug_assert!(!user_data.is_null(), "on_auth user_data is null");
+                    let user_data: &mut InternalUserData =
+                        unsafe { &mut *(user_data as *mut InternalUserData) };
+                    let event_data: &mut mosquitto_evt_extended_auth =
+                        unsafe { &mut *(event_data as *mut mosquitto_evt_extended_auth) };
+
+                    let method: Option<&str> = if event_data.auth_method.is_null() {
+                        None
+                    } else {
+                        match debug_assert_null_or_str!(
+                            event_data.auth_method,
+                            "extended auth denied, the auth method is not valid UTF-8"
+                        ) {
+                            Some(method) => Some(method),
+                            None => return Error::Auth.into(),
+                        }
+                    };
+
+                    let data_in = (!event_data.data_in.is_null()).then(|| unsafe {
+                        std::slice::from_raw_parts(
+                            event_data.data_in as *const u8,
+                            event_data.data_in_len as usize,
+                        )
+                    });
+
+                    let client = MosquittoClient {
                         client: event_data.client,
-                    },
-                    method,
-                    data_in,
-                )
-            } else {
-                unreachable!("invalid event type");
-            };
-
-            match result {
-                Ok(r) => r.into(),
-                Err(Error::AuthContinue(data_out)) => {
-                    debug_assert!(data_out.len() <= u16::MAX as usize);
-                    event_data.data_out_len = data_out.len() as u16;
-                    event_data.data_out = data_out.as_ptr() as _;
-                    std::mem::forget(data_out);
-                    Error::AuthContinue(Vec::with_capacity(0)).into()
-                }
-                Err(e) => e.into(),
-            }
+                    };
+                    let listener = client.get_listener();
+                    // Mosquitto doesn't tell re-authentication apart, a client that already
+                    // completed extended auth since it connected re-authenticates
+                    let client_key = event_data.client as usize;
+                    let outcome = if event == MosquittoPluginEvent::MosqEvtExtAuthStart as c_int {
+                        let auth = AuthStart {
+                            method,
+                            data: data_in,
+                            reauth: user_data.authenticated.contains(&client_key),
+                        };
+                        user_data.external_user_data.on_auth_start(&client, listener, auth)
+                    } else if event == MosquittoPluginEvent::MosqEvtExtAuthContinue as c_int {
+                        user_data.external_user_data.on_auth_continue(
+                            &client,
+                            listener,
+                            method,
+                            data_in,
+                        )
+                    } else {
+                        unreachable!("invalid event type");
+                    };
+
+                    let accepted = matches!(outcome, AuthOutcome::Accept(_));
+                    let rc = __auth_outcome(
+                        outcome,
+                        &mut event_data.data_out,
+                        &mut event_data.data_out_len,
+                    );
+                    if accepted && rc == 0 {
+                        user_data.authenticated.insert(client_key);
+                    }
+                    rc
+                },
+            )
         }
 
-        #[no_mangle]
         extern "C" fn on_control_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on_control_trampoline event_data is null"
-            );
-            debug_assert!(
-                !user_data.is_null(),
-                "on_control_trampoline user_data is null"
-            );
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_control =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_control) };
-
-            let topic: &str = debug_assert_null_or_str!(
-                event_data.topic,
-                "control trampoline failed to create topic &str from CStr pointer"
-            );
-
-            let payload: &[u8] = debug_assert_null_or_slice!(
-                event_data.payload,
-                event_data.payloadlen,
-                "on_control_trampoline payload is null"
-            );
-
-            let msg = MosquittoMessage {
-                topic,
-                payload,
-                qos: event_data.qos.into(),
-                retain: event_data.retain,
-            };
-
-            user_data.external_user_data.on_control(
-                &MosquittoClient {
-                    client: event_data.client,
+            __catch_panic(
+                <$t>::PANIC_POLICY,
+                "on_control",
+                Error::Unknown,
+                Error::PluginDefer.into(),
+                || {
+                    debug_assert!(
+                        !event_data.is_null(),
+                        "on_control_trampoline event_data is null"
+                    );
+                    debug_assert!(
+                        !user_data.is_null(),
+                        "on_control_trampoline user_data is null"
+                    );
+                    let user_data: &mut InternalUserData =
+                        unsafe { &mut *(user_data as *mut InternalUserData) };
+                    let event_data: &mut mosquitto_evt_control =
+                        unsafe { &mut *(event_data as *mut mosquitto_evt_control) };
+
+                    let topic: &str = match debug_assert_null_or_str!(
+                        event_data.topic,
+                        "control message ignored, the topic is not valid UTF-8"
+                    ) {
+                        Some(topic) => topic,
+                        None => return Error::Inval.into(),
+                    };
+
+                    let payload: &[u8] = debug_assert_null_or_slice!(
+                        event_data.payload,
+                        event_data.payloadlen,
+                        "on_control_trampoline payload is null"
+                    );
+
+                    let msg = MosquittoMessage {
+                        topic,
+                        payload,
+                        qos: event_data.qos.into(),
+                        retain: event_data.retain,
+                        properties: unsafe { Properties::from_ptr(event_data.properties) },
+                    };
+
+                    let client = MosquittoClient {
+                        client: event_data.client,
+                    };
+                    match user_data.external_user_data.on_control(&client, msg) {
+                        Some(response) => __send_control_response(&client, topic, &response),
+                        None => 0,
+                    }
                 },
-                msg,
-            );
-            0
+            )
         }
 
-        #[no_mangle]
         extern "C" fn on_message_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on_message_trampoline event_data is null"
-            );
-            debug_assert!(
-                !user_data.is_null(),
-                "on_message_trampoline user_data is null"
-            );
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_message =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_message) };
-
-            let topic: &str = debug_assert_null_or_str!(
-                event_data.topic,
-                "message trampoline failed to create topic &str from CStr pointer"
-            );
-
-            let payload: &[u8] = debug_assert_null_or_slice!(
-                event_data.payload,
-                event_data.payloadlen,
-                "on_message_trampoline_is_null"
-            );
-
-            let msg = MosquittoMessage {
-                topic,
-                payload,
-                qos: event_data.qos.into(),
-                retain: event_data.retain,
-            };
+            __catch_panic(<$t>::PANIC_POLICY, "on_message", Error::AclDenied, Success.into(), || {
+                debug_assert!(
+                    !event_data.is_null(),
+                    "on_message_trampoline event_data is null"
+                );
+                debug_assert!(
+                    !user_data.is_null(),
+                    "on_message_trampoline user_data is null"
+                );
+                let user_data: &mut InternalUserData =
+                    unsafe { &mut *(user_data as *mut InternalUserData) };
+                let event_data: &mut mosquitto_evt_message =
+                    unsafe { &mut *(event_data as *mut mosquitto_evt_message) };
+
+                let topic: &str = match debug_assert_null_or_str!(
+                    event_data.topic,
+                    "message dropped, the topic is not valid UTF-8"
+                ) {
+                    Some(topic) => topic,
+                    None => return Error::AclDenied.into(),
+                };
 
-            user_data.external_user_data.on_message(
-                &MosquittoClient {
-                    client: event_data.client,
-                },
-                msg,
-            );
-            0
+                let payload: &[u8] = debug_assert_null_or_slice!(
+                    event_data.payload,
+                    event_data.payloadlen,
+                    "on_message_trampoline_is_null"
+                );
+
+                let msg = MosquittoMessage {
+                    topic,
+                    payload,
+                    qos: event_data.qos.into(),
+                    retain: event_data.retain,
+                    properties: unsafe { Properties::from_ptr(event_data.properties) },
+                };
+
+                let action = user_data.external_user_data.on_message(
+                    &MosquittoClient {
+                        client: event_data.client,
+                    },
+                    msg,
+                );
+
+                match action {
+                    MessageAction::Pass => Success.into(),
+                    // The broker silently discards messages that are denied here
+                    MessageAction::Drop => Error::AclDenied.into(),
+                    // Any other error makes mosquitto 2.0 disconnect the publisher. A replacement
+                    // that fails validation left the message untouched, so it is passed on.
+                    MessageAction::Replace(message) => {
+                        if let Err(e) = __replace_message(event_data, message) {
+                            mosquitto_warn!(
+                                "Failed to replace message in flight, passing it unchanged: {:?}",
+                                e
+                            );
+                        }
+                        Success.into()
+                    }
+                }
+            })
         }
 
-        #[no_mangle]
         extern "C" fn on_psk_key_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on_psk_key_trampoline event_data is null"
-            );
-            debug_assert!(
-                !user_data.is_null(),
-                "on_psk_key_trampoline user_data is null"
-            );
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_psk_key =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_psk_key) };
-
-            let hint: &str = debug_assert_null_or_str!(
-                event_data.hint,
-                "psk key trampoline failed to create hint &str from CStr pointer"
-            );
-
-            let identity: &str = debug_assert_null_or_str!(
-                event_data.identity,
-                "psk key trampoline failed to create identity &str from CStr pointer"
-            );
-
-            let key: &str = debug_assert_null_or_str!(
-                event_data.key,
-                "psk key trampoline failed to create key &str from CStr pointer"
-            );
-
-            user_data.external_user_data.on_psk(
-                &MosquittoClient {
-                    client: event_data.client,
-                },
-                hint,
-                identity,
-                key,
-                event_data.max_key_len as i32,
-            )
+            __catch_panic(<$t>::PANIC_POLICY, "on_psk", Error::Auth, Error::PluginDefer.into(), || {
+                debug_assert!(
+                    !event_data.is_null(),
+                    "on_psk_key_trampoline event_data is null"
+                );
+                debug_assert!(
+                    !user_data.is_null(),
+                    "on_psk_key_trampoline user_data is null"
+                );
+                let user_data: &mut InternalUserData =
+                    unsafe { &mut *(user_data as *mut InternalUserData) };
+                let event_data: &mut mosquitto_evt_psk_key =
+                    unsafe { &mut *(event_data as *mut mosquitto_evt_psk_key) };
+
+                // TLS-PSK hints and identities are opaque to the broker, they are passed on as is
+                let hint: &std::ffi::CStr = unsafe {
+                    debug_assert!(!event_data.hint.is_null(), "psk key hint is null");
+                    std::ffi::CStr::from_ptr(event_data.hint)
+                };
+
+                let identity: &std::ffi::CStr = unsafe {
+                    debug_assert!(!event_data.identity.is_null(), "psk key identity is null");
+                    std::ffi::CStr::from_ptr(event_data.identity)
+                };
+
+                // The key buffer is an output, the key is written into it hex encoded
+                let key = user_data.external_user_data.on_psk(
+                    &MosquittoClient {
+                        client: event_data.client,
+                    },
+                    hint,
+                    identity,
+                );
+                __write_psk_key(key, event_data.key, event_data.max_key_len)
+            })
         }
 
-        #[no_mangle]
         extern "C" fn on_tick_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on_tick_trampoline event_data is null"
-            );
-            debug_assert!(!user_data.is_null(), "on_tick_trampoline user_data is null");
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-            let event_data: &mut mosquitto_evt_tick =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_tick) };
-
-            user_data.external_user_data.on_tick(
-                event_data.now_ns as i64,
-                event_data.next_ns as i64,
-                event_data.now_s as i32,
-                event_data.next_s as i32,
-            );
-            0
+            __catch_panic(<$t>::PANIC_POLICY, "on_tick", Error::Unknown, Success.into(), || {
+                debug_assert!(
+                    !event_data.is_null(),
+                    "on_tick_trampoline event_data is null"
+                );
+                debug_assert!(!user_data.is_null(), "on_tick_trampoline user_data is null");
+                let user_data: &mut InternalUserData =
+                    unsafe { &mut *(user_data as *mut InternalUserData) };
+                let event_data: &mut mosquitto_evt_tick =
+                    unsafe { &mut *(event_data as *mut mosquitto_evt_tick) };
+
+                let now = __tick_time(event_data.now_s as i64, event_data.now_ns as i64);
+                $crate::scheduler::run_due(&mut user_data.external_user_data, now, <$t>::scheduler);
+                user_data.external_user_data.on_tick(
+                    now.subsec_nanos() as i64,
+                    event_data.next_ns as i64,
+                    now.as_secs() as i32,
+                    event_data.next_s as i32,
+                );
+                0
+            })
         }
 
-        #[no_mangle]
         extern "C" fn on_disconnect_trampoline(
             _event: c_int,
             event_data: *mut c_void,
             user_data: *mut c_void,
         ) -> c_int {
-            debug_assert!(
-                !event_data.is_null(),
-                "on disconnect_trampoline event_data is null"
-            );
-            debug_assert!(
-                !user_data.is_null(),
-                "on disconnect_trampoline user_data is null"
-            );
-            let user_data: &mut InternalUserData = unsafe {
-                // mosquitto_debug!("Got user data: {:?}", user_data);
-                &mut *(user_data as *mut InternalUserData)
-            };
+            __catch_panic(<$t>::PANIC_POLICY, "on_disconnect", Error::Unknown, Success.into(), || {
+                debug_assert!(
+                    !event_data.is_null(),
+                    "on disconnect_trampoline event_data is null"
+                );
+                debug_assert!(
+                    !user_data.is_null(),
+                    "on disconnect_trampoline user_data is null"
+                );
+                let user_data: &mut InternalUserData = unsafe {
+                    // mosquitto_debug!("Got user data: {:?}", user_data);
+                    &mut *(user_data as *mut InternalUserData)
+                };
 
-            let event_data: &mut mosquitto_evt_disconnect =
-                unsafe { &mut *(event_data as *mut mosquitto_evt_disconnect) };
+                let event_data: &mut mosquitto_evt_disconnect =
+                    unsafe { &mut *(event_data as *mut mosquitto_evt_disconnect) };
 
-            let client = MosquittoClient {
-                client: event_data.client,
-            };
+                let client = MosquittoClient {
+                    client: event_data.client,
+                };
+
+                user_data
+                    .authenticated
+                    .remove(&(event_data.client as usize));
+                // Also registered for extended auth only
+                if <$t>::EVENTS.contains(MosquittoPluginEvent::MosqEvtDisconnect) {
+                    user_data
+                        .external_user_data
+                        .on_disconnect(&client, event_data.reason.into());
+                }
+
+                0
+            })
+        }
 
-            user_data
-                .external_user_data
-                .on_disconnect(&client, event_data.reason);
+        // Events added in mosquitto 2.1
+        $crate::__mosq_2_1! {
+            extern "C" fn on_connect_trampoline(
+                _event: c_int,
+                event_data: *mut c_void,
+                user_data: *mut c_void,
+            ) -> c_int {
+                __catch_panic(<$t>::PANIC_POLICY, "on_connect", Error::Unknown, Success.into(), || {
+                    debug_assert!(
+                        !event_data.is_null(),
+                        "on_connect_trampoline event_data is null"
+                    );
+                    debug_assert!(!user_data.is_null(), "on_connect_trampoline user_data is null");
+                    let user_data: &mut InternalUserData =
+                        unsafe { &mut *(user_data as *mut InternalUserData) };
+                    let event_data: &mut mosquitto_evt_connect =
+                        unsafe { &mut *(event_data as *mut mosquitto_evt_connect) };
+
+                    user_data.external_user_data.on_connect(&MosquittoClient {
+                        client: event_data.client,
+                    });
+                    0
+                })
+            }
+
+            extern "C" fn on_subscribe_trampoline(
+                event: c_int,
+                event_data: *mut c_void,
+                user_data: *mut c_void,
+            ) -> c_int {
+                __catch_panic(
+                    <$t>::PANIC_POLICY,
+                    "on_subscribe",
+                    Error::AclDenied,
+                    Error::PluginDefer.into(),
+                    || {
+                        debug_assert!(
+                            !event_data.is_null(),
+                            "on_subscribe_trampoline event_data is null"
+                        );
+                        debug_assert!(
+                            !user_data.is_null(),
+                            "on_subscribe_trampoline user_data is null"
+                        );
+                        let user_data: &mut InternalUserData =
+                            unsafe { &mut *(user_data as *mut InternalUserData) };
+                        // Subscribe and unsubscribe events share their layout
+                        let event_data: &mut mosquitto_evt_subscribe =
+                            unsafe { &mut *(event_data as *mut mosquitto_evt_subscribe) };
+
+                        let topic_filter = match debug_assert_null_or_str!(
+                            event_data.data.topic_filter,
+                            "subscription denied, the topic filter is not valid UTF-8"
+                        ) {
+                            Some(topic_filter) => topic_filter,
+                            None => return Error::AclDenied.into(),
+                        };
+                        let subscription = Subscription {
+                            topic_filter,
+                            options: event_data.data.options,
+                            identifier: event_data.data.identifier,
+                            properties: unsafe { Properties::from_ptr(event_data.data.properties) },
+                        };
+                        let client = MosquittoClient {
+                            client: event_data.client,
+                        };
+                        let result = if event == MosquittoPluginEvent::MosqEvtSubscribe as c_int {
+                            user_data.external_user_data.on_subscribe(&client, subscription)
+                        } else {
+                            user_data.external_user_data.on_unsubscribe(&client, subscription)
+                        };
+                        match result {
+                            Ok(s) => s.into(),
+                            Err(e) => e.into(),
+                        }
+                    },
+                )
+            }
 
-            0
+            extern "C" fn on_client_offline_trampoline(
+                _event: c_int,
+                event_data: *mut c_void,
+                user_data: *mut c_void,
+            ) -> c_int {
+                __catch_panic(
+                    <$t>::PANIC_POLICY,
+                    "on_client_offline",
+                    Error::Unknown,
+                    Success.into(),
+                    || {
+                        debug_assert!(
+                            !event_data.is_null(),
+                            "on_client_offline_trampoline event_data is null"
+                        );
+                        debug_assert!(
+                            !user_data.is_null(),
+                            "on_client_offline_trampoline user_data is null"
+                        );
+                        let user_data: &mut InternalUserData =
+                            unsafe { &mut *(user_data as *mut InternalUserData) };
+                        let event_data: &mut mosquitto_evt_client_offline =
+                            unsafe { &mut *(event_data as *mut mosquitto_evt_client_offline) };
+
+                        let client = MosquittoClient {
+                            client: event_data.client,
+                        };
+                        user_data
+                            .external_user_data
+                            .on_client_offline(&client, event_data.reason.into());
+                        0
+                    },
+                )
+            }
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn mosquitto_plugin_init(
             identifier: *mut c_void,
             user_data: *mut *mut c_void, // When this pointer is set, every other call will get this pointer as well. Only for v4 plugins?
             opts: *mut mosquitto_opt,
             opt_count: c_int,
         ) -> c_int {
-            let opts = __from_ptr_and_size(opts, opt_count as _);
-            mosquitto_debug!("mosquitto_plugin_init {:?}", opts);
-
-            let instance: $t = <$t>::init(opts);
-            let instance = instance;
-            mosquitto_debug!("external_user_data addr {:?}", instance);
-            let internal_user_data = InternalUserData {
-                identifier,
-                external_user_data: instance,
-            };
-            let internal_user_data = Box::new(internal_user_data);
-            let instance_rawptr: *mut InternalUserData = Box::into_raw(internal_user_data);
+            __catch_panic(<$t>::PANIC_POLICY, "init", Error::Unknown, Error::Unknown.into(), || {
+                let opts = match __from_ptr_and_size(opts, opt_count as _) {
+                    Ok(opts) => opts,
+                    Err(e) => return __init_failed(e),
+                };
+                mosquitto_debug!("mosquitto_plugin_init {:?}", opts);
 
-            unsafe {
-                *user_data = instance_rawptr as _;
-            }
+                let instance: $t = match <$t>::init(opts) {
+                    Ok(instance) => instance,
+                    Err(e) => return __init_failed(e),
+                };
+                mosquitto_debug!("external_user_data addr {:?}", instance);
+                let internal_user_data = InternalUserData {
+                    identifier,
+                    external_user_data: instance,
+                    registrations: __Registrations::default(),
+                    authenticated: std::collections::HashSet::new(),
+                };
+                let internal_user_data = Box::new(internal_user_data);
+                let instance_rawptr: *mut InternalUserData = Box::into_raw(internal_user_data);
+
+                unsafe {
+                    *user_data = instance_rawptr as _;
+                }
 
-            unsafe {
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtReload as _,
+                // Registers the callbacks of the events the plugin declared, recording those
+                // mosquitto accepted so cleanup unregisters exactly them. A control topic that
+                // fails is only logged, any other event fails the init, the broker must not run
+                // without the ACL or auth checks of the plugin.
+                let mut registrations = __Registrations::default();
+                let mut failed: Option<(MosquittoPluginEvent, Error)> = None;
+                let mut register =
+                    |event: MosquittoPluginEvent,
+                     callback: MOSQ_FUNC_generic_callback,
+                     event_data: Option<std::ffi::CString>| {
+                        if __registered_events(<$t>::EVENTS).contains(event) {
+                            let result = registrations.register(
+                                identifier,
+                                event,
+                                callback,
+                                event_data,
+                                instance_rawptr as _,
+                            );
+                            if let Err(e) = result {
+                                if event != MosquittoPluginEvent::MosqEvtControl && failed.is_none()
+                                {
+                                    failed = Some((event, e));
+                                }
+                            }
+                        }
+                    };
+
+                register(
+                    MosquittoPluginEvent::MosqEvtReload,
                     Some(on_reload_trampoline),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtAclCheck as _,
+                register(
+                    MosquittoPluginEvent::MosqEvtAclCheck,
                     Some(on_acl_check_trampoline),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtBasicAuth as _,
+                register(
+                    MosquittoPluginEvent::MosqEvtBasicAuth,
                     Some(on_basic_auth_trampoline),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtExtAuthStart as _,
+                register(
+                    MosquittoPluginEvent::MosqEvtExtAuthStart,
                     Some(on_auth),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtExtAuthContinue as _,
+                register(
+                    MosquittoPluginEvent::MosqEvtExtAuthContinue,
                     Some(on_auth),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                let event_data = "$CONTROL";
-                let cstr = &std::ffi::CString::new(event_data).unwrap();
-                let bytes = cstr.as_bytes_with_nul();
-                let topic = bytes.as_ptr() as *const c_void;
-                // TODO the event_data parameter (4th param) has a meaning for the MOSQ_EVT_CONTROL callback
-                // Something to do with the topic the control events are triggered on?
-                //https://github.com/eclipse/mosquitto/blob/master/plugins/dynamic-security/plugin.c#L494
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtControl as _,
-                    Some(on_control_trampoline),
-                    topic,
-                    instance_rawptr as _,
-                );
-
-                let res = mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtMessage as _,
+                // Control callbacks are registered per topic, passed as the event data. The
+                // registrations keep the topics to unregister them with at cleanup.
+                for topic in <$t>::CONTROL_TOPICS {
+                    match std::ffi::CString::new(*topic) {
+                        Ok(topic) => register(
+                            MosquittoPluginEvent::MosqEvtControl,
+                            Some(on_control_trampoline),
+                            Some(topic),
+                        ),
+                        Err(_) => mosquitto_warn!("control topic {:?} contains a nul byte", topic),
+                    }
+                }
+                register(
+                    MosquittoPluginEvent::MosqEvtMessage,
                     Some(on_message_trampoline),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtPskKey as _,
+                register(
+                    MosquittoPluginEvent::MosqEvtPskKey,
                     Some(on_psk_key_trampoline),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtTick as _,
+                register(
+                    MosquittoPluginEvent::MosqEvtTick,
                     Some(on_tick_trampoline),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-
-                let res = mosquitto_callback_register(
-                    identifier as _,
-                    MosquittoPluginEvent::MosqEvtDisconnect as _,
+                register(
+                    MosquittoPluginEvent::MosqEvtDisconnect,
                     Some(on_disconnect_trampoline),
-                    std::ptr::null(),
-                    instance_rawptr as _,
+                    None,
                 );
-            }
 
-            Success.into()
+                $crate::__mosq_2_1! {
+                    register(
+                        MosquittoPluginEvent::MosqEvtConnect,
+                        Some(on_connect_trampoline),
+                        None,
+                    );
+                    register(
+                        MosquittoPluginEvent::MosqEvtSubscribe,
+                        Some(on_subscribe_trampoline),
+                        None,
+                    );
+                    register(
+                        MosquittoPluginEvent::MosqEvtUnsubscribe,
+                        Some(on_subscribe_trampoline),
+                        None,
+                    );
+                    register(
+                        MosquittoPluginEvent::MosqEvtClientOffline,
+                        Some(on_client_offline_trampoline),
+                        None,
+                    );
+                }
+
+                if let Some((event, e)) = failed {
+                    registrations.unregister_all(identifier);
+                    unsafe {
+                        *user_data = std::ptr::null_mut();
+                        drop(Box::from_raw(instance_rawptr));
+                    }
+                    let message = format!("failed to register the callback for {:?}", event);
+                    return __init_failed(InitError::new(e, message));
+                }
+
+                $crate::__mosq_2_1! {
+                    __set_plugin_info(identifier, <$t>::PLUGIN_NAME, <$t>::PLUGIN_VERSION);
+                }
+
+                unsafe {
+                    (*instance_rawptr).registrations = registrations;
+                }
+
+                Success.into()
+            })
         }
 
         #[no_mangle]
@@ -572,30 +838,47 @@ macro_rules! create_dynamic_library {
             opts: *mut mosquitto_opt,
             opt_count: c_int,
         ) -> c_int {
-            let opts = __from_ptr_and_size(opts, opt_count as _);
-            let user_data: &mut InternalUserData =
-                unsafe { &mut *(user_data as *mut InternalUserData) };
-
-            unsafe {
-                mosquitto_callback_unregister(
-                    user_data.identifier as _,
-                    MosquittoPluginEvent::MosqEvtDisconnect as _,
-                    Some(on_disconnect_trampoline),
-                    std::ptr::null(),
-                );
-            }
-            if !user_data.identifier.is_null() {
-                let identifier = unsafe {
-                    let c_str = std::ffi::CStr::from_ptr(user_data.identifier as _);
-                    c_str
-                        .to_str()
-                        .expect("plugin identifier is null at cleanup")
-                };
-                mosquitto_debug!("cleaning up plugin: {}", identifier);
-            }
-            drop(unsafe { Box::from_raw(user_data as *mut InternalUserData) });
-
-            Success.into()
+            __catch_panic(<$t>::PANIC_POLICY, "cleanup", Error::Unknown, Error::Unknown.into(), || {
+                // mosquitto cleans up plugins whose init failed as well, there is no instance then
+                if user_data.is_null() {
+                    mosquitto_debug!("cleaning up a plugin that failed to initialize");
+                    return Success.into();
+                }
+                // The instance is cleaned up even if the options changed into invalid ones
+                let opts = __from_ptr_and_size(opts, opt_count as _).unwrap_or_else(|e| {
+                    mosquitto_warn!("cleaning up without options: {}", e.message);
+                    Default::default()
+                });
+                let user_data: &mut InternalUserData =
+                    unsafe { &mut *(user_data as *mut InternalUserData) };
+
+                // No callback may run with the instance once it is freed
+                user_data.registrations.unregister_all(user_data.identifier);
+                user_data.external_user_data.cleanup(opts);
+                // The identifier is opaque, it is only told apart by its address
+                mosquitto_debug!("cleaning up plugin: {:?}", user_data.identifier);
+                drop(unsafe { Box::from_raw(user_data as *mut InternalUserData) });
+
+                Success.into()
+            })
         }
     };
 }
+
+// Expands its input only when building for the mosquitto 2.1 plugin API. The features are
+// checked here, as a cfg inside create_dynamic_library! would be evaluated in the plugin crate.
+#[cfg(feature = "mosq-2-1")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __mosq_2_1 {
+    ($($tokens:tt)*) => {
+        $($tokens)*
+    };
+}
+
+#[cfg(not(feature = "mosq-2-1"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __mosq_2_1 {
+    ($($tokens:tt)*) => {};
+}
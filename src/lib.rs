@@ -4,45 +4,75 @@ pub mod mosquitto_dev;
 pub use mosquitto_dev::*;
 
 use std::collections::HashMap;
-use std::convert::From;
+use std::convert::{From, TryFrom};
 use std::ffi::CString;
 use std::fmt;
 
+#[cfg(feature = "scram")]
+pub mod auth;
+pub mod certificate;
 pub mod dynlib;
+#[cfg(feature = "serde")]
+pub mod options;
+pub mod properties;
+pub mod psk;
+pub mod scheduler;
+// The mock broker of mosquitto-plugin-testing, built into the unit tests of this crate. Taking
+// it as a dev-dependency would give the tests a second copy of the types of this crate.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../testing/src/lib.rs"]
+mod testing;
+// The path the mock broker refers to this crate by
+#[cfg(test)]
+extern crate self as mosquitto_plugin;
+
+pub use certificate::Certificate;
+pub use properties::{OwnedProperties, Properties};
+pub use psk::PskKey;
 
 pub use libc;
+
+#[cfg(all(feature = "mosq-2-0", feature = "mosq-2-1"))]
+compile_error!("the `mosq-2-0` and `mosq-2-1` features are mutually exclusive");
+#[cfg(not(any(feature = "mosq-2-0", feature = "mosq-2-1")))]
+compile_error!("one of the `mosq-2-0` or `mosq-2-1` features has to be enabled");
 use std::net::IpAddr;
 use std::str::FromStr;
 
 pub type MosquittoOpt<'a> = HashMap<&'a str, &'a str>;
 
-// parses the pointers given by mosquitto into a rust native structure
-pub fn __from_ptr_and_size<'a>(opts: *mut mosquitto_opt, count: usize) -> MosquittoOpt<'a> {
+// parses the pointers given by mosquitto into a rust native structure. An option that isn't
+// valid UTF-8 is an error, options without a key or value are skipped.
+pub fn __from_ptr_and_size<'a>(
+    opts: *mut mosquitto_opt,
+    count: usize,
+) -> Result<MosquittoOpt<'a>, InitError> {
     let mut map = HashMap::new();
-    // Yep, raw pointer values
-    let optsval = opts as usize;
-    for i in 0..count {
-        // manually increment the pointers according to the coun value
-        let opt = unsafe {
-            let opt = optsval + i * std::mem::size_of::<mosquitto_opt>();
-            (opt as *mut mosquitto_opt)
-                .as_ref()
-                .expect("Failed to extract from ptr and size")
-        };
-
-        // get a reference, and then use this to parse the values into owned strings
-        let key: &str = unsafe {
-            let c_str = std::ffi::CStr::from_ptr(opt.key);
-            c_str.to_str().expect("Failed to create string from CStr")
-        };
-        let value: &str = unsafe {
-            let c_str = std::ffi::CStr::from_ptr(opt.value);
-            c_str.to_str().expect("Failed to create string from CStr")
-        };
-        map.insert(key, value);
+    if opts.is_null() {
+        return Ok(map);
+    }
+    let opts = unsafe { std::slice::from_raw_parts(opts as *const mosquitto_opt, count) };
+    for opt in opts {
+        if opt.key.is_null() || opt.value.is_null() {
+            continue;
+        }
+        let key = unsafe { std::ffi::CStr::from_ptr(opt.key) };
+        let value = unsafe { std::ffi::CStr::from_ptr(opt.value) };
+        match (key.to_str(), value.to_str()) {
+            (Ok(key), Ok(value)) => {
+                map.insert(key, value);
+            }
+            _ => {
+                return Err(InitError::invalid_options(format!(
+                    "option {} is not valid UTF-8",
+                    key.to_string_lossy()
+                )))
+            }
+        }
     }
 
-    map
+    Ok(map)
 }
 
 #[repr(C)]
@@ -236,6 +266,367 @@ impl From<Success> for i32 {
     }
 }
 
+/// Why `MosquittoPlugin::init` failed. The message is logged as an error and the code returned
+/// from mosquitto_plugin_init, which keeps the broker from starting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitError {
+    pub error: Error,
+    pub message: String,
+}
+
+impl InitError {
+    pub fn new(error: Error, message: impl Into<String>) -> InitError {
+        InitError {
+            error,
+            message: message.into(),
+        }
+    }
+
+    /// Missing or invalid options, returned as MOSQ_ERR_INVAL
+    pub fn invalid_options(message: impl Into<String>) -> InitError {
+        InitError::new(Error::Inval, message)
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?})", self.message, self.error)
+    }
+}
+
+impl std::error::Error for InitError {}
+
+impl From<std::io::Error> for InitError {
+    fn from(e: std::io::Error) -> InitError {
+        InitError::new(Error::Errno, e.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<options::OptionsError> for InitError {
+    fn from(e: options::OptionsError) -> InitError {
+        InitError::invalid_options(e.to_string())
+    }
+}
+
+// The time of a tick event. Mosquitto 2.0 leaves it zero, then the monotonic clock the broker
+// keeps its own time with is read instead.
+pub fn __tick_time(now_s: i64, now_ns: i64) -> std::time::Duration {
+    if now_s > 0 || now_ns > 0 {
+        std::time::Duration::from_secs(now_s as u64)
+            + std::time::Duration::from_nanos(now_ns as u64)
+    } else {
+        scheduler::monotonic_now()
+    }
+}
+
+// Answers a PSK key event: writes the key into the buffer of the event, or defers to other
+// plugins and the psk_file without one. A key too long for the buffer fails the handshake.
+pub fn __write_psk_key(
+    key: Option<PskKey>,
+    buffer: *mut std::os::raw::c_char,
+    max_key_len: std::os::raw::c_int,
+) -> i32 {
+    match key.map(|key| key.write_to(buffer, max_key_len)) {
+        Some(Ok(())) => Success.into(),
+        Some(Err(e)) => {
+            mosquitto_warn!(
+                "the PSK key does not fit the {} hex characters mosquitto allows",
+                max_key_len
+            );
+            e.into()
+        }
+        None => Error::PluginDefer.into(),
+    }
+}
+
+// Sends the response of a control handler to the client that sent the request, on the request
+// topic with /response appended, like the dynamic security plugin does
+pub fn __send_control_response(
+    client: &dyn MosquittoClientContext,
+    topic: &str,
+    response: &[u8],
+) -> i32 {
+    let client_id = match client.get_id() {
+        Some(client_id) => client_id,
+        None => {
+            mosquitto_warn!(
+                "control response on {} dropped, the client has no id",
+                topic
+            );
+            return Error::Inval.into();
+        }
+    };
+    let result = mosquitto_calls::Publish::new(format!("{}/response", topic))
+        .payload(response)
+        .to_client(client_id)
+        .send();
+    match result {
+        Ok(success) => success.into(),
+        Err(e) => {
+            mosquitto_warn!("failed to send the control response on {}: {:?}", topic, e);
+            e.into()
+        }
+    }
+}
+
+/// The start of extended authentication, for the CONNECT packet of a client or an AUTH packet
+/// re-authenticating a connected one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthStart<'a> {
+    pub method: Option<&'a str>,
+    pub data: Option<&'a [u8]>,
+    /// Whether a connected client re-authenticates. Mosquitto doesn't pass the flag to plugins,
+    /// it is set when the client completed extended authentication with this plugin instance
+    /// since it connected.
+    pub reauth: bool,
+}
+
+/// The answer of a plugin to a step of extended authentication
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthOutcome {
+    /// The client is authenticated, the data is sent with the CONNACK or AUTH packet
+    Accept(Option<Vec<u8>>),
+    /// Sends the data to the client in an AUTH packet and waits for its answer
+    Continue(Vec<u8>),
+    Deny,
+    /// Leaves the decision to other plugins
+    Defer,
+}
+
+// Hands the outcome of an extended auth callback to mosquitto. Data for the client is copied into
+// a buffer from mosquitto_malloc, the broker frees it after sending.
+pub fn __auth_outcome(
+    outcome: AuthOutcome,
+    data_out: &mut *mut std::os::raw::c_void,
+    data_out_len: &mut u16,
+) -> i32 {
+    let (data, rc) = match outcome {
+        AuthOutcome::Accept(data) => (data, Success.into()),
+        AuthOutcome::Continue(data) => (Some(data), Error::AuthContinue(Vec::new()).into()),
+        AuthOutcome::Deny => (None, Error::Auth.into()),
+        AuthOutcome::Defer => (None, Error::PluginDefer.into()),
+    };
+    let data = match data {
+        Some(data) if !data.is_empty() => data,
+        _ => return rc,
+    };
+    if data.len() > u16::MAX as usize {
+        mosquitto_warn!(
+            "extended auth denied, {} bytes of auth data don't fit the 65535 of an AUTH packet",
+            data.len()
+        );
+        return Error::Auth.into();
+    }
+    let buffer = unsafe { mosquitto_malloc(data.len()) };
+    if buffer.is_null() {
+        return Error::NoMem.into();
+    }
+    unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buffer as *mut u8, data.len()) };
+    *data_out = buffer;
+    *data_out_len = data.len() as u16;
+    rc
+}
+
+// The events registered for a plugin. Extended auth needs the disconnect event as well, to
+// forget the clients it authenticated.
+pub fn __registered_events(events: EventSet) -> EventSet {
+    if events.contains(MosquittoPluginEvent::MosqEvtExtAuthStart) {
+        events | EventSet::DISCONNECT
+    } else {
+        events
+    }
+}
+
+/// How a plugin takes the new options on reload
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReloadPolicy {
+    /// Call `on_reload`, which updates the running instance in place
+    InPlace,
+    /// Build a new instance with `rebuild` and swap it in only if that succeeds. On failure the
+    /// error is logged and the running instance keeps its previous options.
+    Rebuild,
+}
+
+// Logs why init failed and returns the code for mosquitto_plugin_init
+pub fn __init_failed(e: InitError) -> i32 {
+    log_init_error("plugin init failed", e)
+}
+
+// Logs why rebuilding on reload failed and returns the code for the reload callback
+pub fn __rebuild_failed(e: InitError) -> i32 {
+    log_init_error("plugin reload failed, keeping the previous options", e)
+}
+
+fn log_init_error(context: &str, e: InitError) -> i32 {
+    let message = format!("{}: {}", context, e.message);
+    mosquitto_calls::mosquitto_log(
+        mosquitto_calls::LogLevel::Err,
+        &message.replace('\0', "\\0"),
+    );
+    e.error.into()
+}
+
+/// How a plugin callback answers mosquitto when the plugin code panics.
+/// The panic is always caught before it reaches the broker, and its message logged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Deny whatever was asked for. ACL checks return AclDenied, authentication returns Auth,
+    /// messages are dropped and failing init keeps the broker from starting.
+    Deny,
+    /// Leave the decision to other plugins or the broker configuration. ACL checks,
+    /// authentication, PSK keys, subscriptions and control messages return PluginDefer.
+    /// Events without a decision to leave return success, messages are passed on unchanged.
+    /// A panic in init still fails it.
+    Defer,
+    /// Abort the broker process
+    Abort,
+}
+
+// Runs the body of a callback, turning a panic into the return code the policy asks for.
+// `denied` and `deferred` are what the Deny and Defer policies return for the event at hand.
+pub fn __catch_panic<F>(
+    policy: PanicPolicy,
+    callback: &str,
+    denied: Error,
+    deferred: i32,
+    body: F,
+) -> i32
+where
+    F: FnOnce() -> i32,
+{
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)) {
+        Ok(res) => res,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("Box<dyn Any>");
+            let message = format!("plugin panicked in {}: {}", callback, message);
+            mosquitto_calls::mosquitto_log(
+                mosquitto_calls::LogLevel::Err,
+                &message.replace('\0', "\\0"),
+            );
+            match policy {
+                PanicPolicy::Deny => denied.into(),
+                PanicPolicy::Defer => deferred,
+                PanicPolicy::Abort => std::process::abort(),
+            }
+        }
+    }
+}
+
+// Answers mosquitto_plugin_version: the plugin API version of the bindings, if the broker offers
+// it, -1 otherwise.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn __plugin_version(count: std::os::raw::c_int, versions: *const std::os::raw::c_int) -> i32 {
+    let version = MOSQ_PLUGIN_VERSION as i32;
+    if versions.is_null() || count <= 0 {
+        return -1;
+    }
+    let versions = unsafe { std::slice::from_raw_parts(versions, count as usize) };
+    if versions.contains(&version) {
+        version
+    } else {
+        -1
+    }
+}
+
+// The callbacks a plugin instance registered with mosquitto. Only registrations mosquitto
+// accepted are recorded, together with their event data, so cleanup can unregister each of
+// them with the same arguments before the instance is freed.
+#[derive(Debug, Default)]
+pub struct __Registrations(
+    Vec<(
+        MosquittoPluginEvent,
+        MOSQ_FUNC_generic_callback,
+        Option<CString>,
+    )>,
+);
+
+impl __Registrations {
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn register(
+        &mut self,
+        identifier: *mut std::os::raw::c_void,
+        event: MosquittoPluginEvent,
+        callback: MOSQ_FUNC_generic_callback,
+        event_data: Option<CString>,
+        userdata: *mut std::os::raw::c_void,
+    ) -> Result<Success, Error> {
+        let res = unsafe {
+            mosquitto_callback_register(
+                identifier as _,
+                event.into(),
+                callback,
+                event_data
+                    .as_ref()
+                    .map_or(std::ptr::null(), |data| data.as_ptr() as _),
+                userdata,
+            )
+        };
+        if res != 0 {
+            let err = Error::from(res);
+            let on = event_data
+                .as_ref()
+                .map(|data| format!(" on {}", data.to_string_lossy()))
+                .unwrap_or_default();
+            mosquitto_calls::mosquitto_log(
+                mosquitto_calls::LogLevel::Warning,
+                &format!(
+                    "failed to register callback for {:?}{}: {:?}",
+                    event, on, err
+                ),
+            );
+            return Err(err);
+        }
+        self.0.push((event, callback, event_data));
+        Ok(Success)
+    }
+
+    // Unregisters the callbacks in reverse order of registration
+    pub fn unregister_all(&mut self, identifier: *mut std::os::raw::c_void) {
+        while let Some((event, callback, event_data)) = self.0.pop() {
+            unsafe {
+                mosquitto_callback_unregister(
+                    identifier as _,
+                    event.into(),
+                    callback,
+                    event_data
+                        .as_ref()
+                        .map_or(std::ptr::null(), |data| data.as_ptr() as _),
+                );
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// Reports the plugin name and version to the broker, if the plugin set a name
+#[cfg(feature = "mosq-2-1")]
+pub fn __set_plugin_info(
+    identifier: *mut std::os::raw::c_void,
+    name: Option<&str>,
+    version: Option<&str>,
+) {
+    let name = match name.map(CString::new) {
+        Some(Ok(name)) => name,
+        _ => return,
+    };
+    let version = version.and_then(|version| CString::new(version).ok());
+    unsafe {
+        mosquitto_plugin_set_info(
+            identifier as _,
+            name.as_ptr(),
+            version.as_ref().map_or(std::ptr::null(), |v| v.as_ptr()),
+        );
+    }
+}
+
 // #[repr(C)]
 // #[derive(Debug)]
 // pub enum QoS {
@@ -260,8 +651,129 @@ pub struct MosquittoMessage<'a> {
     pub payload: &'a [u8],
     pub qos: i32,
     pub retain: bool,
+    /// MQTT v5 properties sent along with the message
+    pub properties: Properties<'a>,
+}
+
+impl<'a> MosquittoMessage<'a> {
+    /// Copies the message, e.g. as a starting point for a replacement returned from `on_message`.
+    /// The properties are left out, a replacement keeps those of the message unless it sets
+    /// them, see [`Properties::into_owned`] for a copy.
+    pub fn into_owned(self) -> OwnedMessage {
+        OwnedMessage {
+            topic: self.topic.to_string(),
+            payload: self.payload.to_vec(),
+            qos: self.qos,
+            retain: self.retain,
+            properties: None,
+        }
+    }
 }
 
+/// A subscription a client asks for or ends, mosquitto 2.1 only
+#[cfg(feature = "mosq-2-1")]
+#[derive(Debug)]
+pub struct Subscription<'a> {
+    pub topic_filter: &'a str,
+    /// The MQTT subscription options: maximum qos in bits 0-1, then no local,
+    /// retain as published and retain handling in bits 4-5
+    pub options: u8,
+    /// The subscription identifier, 0 if none was given
+    pub identifier: u32,
+    /// MQTT v5 properties sent along with the (un)subscribe request
+    pub properties: Properties<'a>,
+}
+
+#[cfg(feature = "mosq-2-1")]
+impl<'a> Subscription<'a> {
+    /// The maximum qos granted for the subscription
+    pub fn qos(&self) -> i32 {
+        (self.options & 0x03) as i32
+    }
+}
+
+/// An owned message, handed back to the broker to replace a message in flight
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: i32,
+    pub retain: bool,
+    /// The MQTT v5 properties. When replacing a message, None keeps those of the original
+    /// message, and Some replaces all of them.
+    pub properties: Option<OwnedProperties>,
+}
+
+/// What the broker should do with a message that has been passed to `on_message`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageAction {
+    /// Let the message through unmodified
+    Pass,
+    /// Replace topic, payload, qos, retain flag and properties of the message before it is
+    /// delivered. An invalid replacement, like a qos above 2 or a nul byte in the topic, is
+    /// logged and the original message passed on.
+    Replace(OwnedMessage),
+    /// Drop the message. MQTT v5 clients get a "not authorized" reason code for it.
+    Drop,
+}
+
+// Swaps the message carried by a message event for `message`. The replacement has to be
+// allocated by mosquitto, since the broker takes ownership of it once the callback returns.
+pub fn __replace_message(
+    event_data: &mut mosquitto_evt_message,
+    message: OwnedMessage,
+) -> Result<Success, Error> {
+    if !(0..=2).contains(&message.qos) {
+        return Err(Error::Inval);
+    }
+    let payloadlen = u32::try_from(message.payload.len()).map_err(|_| Error::PayloadSize)?;
+    let topic = CString::new(message.topic).map_err(|_| Error::Inval)?;
+    let properties = match &message.properties {
+        Some(properties) => Some(properties.to_list()?),
+        None => None,
+    };
+
+    unsafe {
+        let topic = mosquitto_strdup(topic.as_ptr());
+        if topic.is_null() {
+            return Err(Error::NoMem);
+        }
+
+        let payload = if message.payload.is_empty() {
+            std::ptr::null_mut()
+        } else {
+            let payload = mosquitto_malloc(message.payload.len());
+            if payload.is_null() {
+                mosquitto_free(topic as _);
+                return Err(Error::NoMem);
+            }
+            std::ptr::copy_nonoverlapping(
+                message.payload.as_ptr(),
+                payload as *mut u8,
+                message.payload.len(),
+            );
+            payload
+        };
+
+        // The broker frees the original payload itself when the pointer changed, but the
+        // original topic and properties are ours to free.
+        mosquitto_free(event_data.topic as _);
+        event_data.topic = topic;
+        event_data.payload = payload;
+        event_data.payloadlen = payloadlen;
+        if let Some(mut properties) = properties {
+            mosquitto_property_free_all(&mut event_data.properties);
+            event_data.properties = properties.as_mut_ptr();
+            properties.forget();
+        }
+    }
+    event_data.qos = message.qos as u8;
+    event_data.retain = message.retain;
+
+    Ok(Success)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum QOS {
     AtMostOnce,
     AtLeastOnce,
@@ -278,44 +790,196 @@ impl QOS {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MosquittoClientProtocol {
     Mqtt,
     MqttSn,
     Websockets,
+    /// A value this crate doesn't know, as returned by mosquitto_client_protocol
+    Unknown(i32),
+}
+
+impl From<i32> for MosquittoClientProtocol {
+    fn from(protocol: i32) -> MosquittoClientProtocol {
+        match protocol as u32 {
+            p if p == mosquitto_protocol_mp_mqtt => MosquittoClientProtocol::Mqtt,
+            p if p == mosquitto_protocol_mp_mqttsn => MosquittoClientProtocol::MqttSn,
+            p if p == mosquitto_protocol_mp_websockets => MosquittoClientProtocol::Websockets,
+            _ => MosquittoClientProtocol::Unknown(protocol),
+        }
+    }
+}
+
+impl From<MosquittoClientProtocol> for i32 {
+    fn from(protocol: MosquittoClientProtocol) -> i32 {
+        match protocol {
+            MosquittoClientProtocol::Mqtt => mosquitto_protocol_mp_mqtt as i32,
+            MosquittoClientProtocol::MqttSn => mosquitto_protocol_mp_mqttsn as i32,
+            MosquittoClientProtocol::Websockets => mosquitto_protocol_mp_websockets as i32,
+            MosquittoClientProtocol::Unknown(protocol) => protocol,
+        }
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MosquittoClientProtocolVersion {
     V3,
     V4,
     V5,
+    /// A value this crate doesn't know, as returned by mosquitto_client_protocol_version
+    Unknown(i32),
+}
+
+impl From<i32> for MosquittoClientProtocolVersion {
+    fn from(version: i32) -> MosquittoClientProtocolVersion {
+        match version {
+            3 => MosquittoClientProtocolVersion::V3,
+            4 => MosquittoClientProtocolVersion::V4,
+            5 => MosquittoClientProtocolVersion::V5,
+            _ => MosquittoClientProtocolVersion::Unknown(version),
+        }
+    }
+}
+
+impl From<MosquittoClientProtocolVersion> for i32 {
+    fn from(version: MosquittoClientProtocolVersion) -> i32 {
+        match version {
+            MosquittoClientProtocolVersion::V3 => 3,
+            MosquittoClientProtocolVersion::V4 => 4,
+            MosquittoClientProtocolVersion::V5 => 5,
+            MosquittoClientProtocolVersion::Unknown(version) => version,
+        }
+    }
+}
+
+/// Why a client disconnected, from the MOSQ_ERR_* code mosquitto passes with the disconnect event.
+///
+/// Mosquitto passes only the code, neither 2.0 nor 2.1 hands the MQTT v5 properties of a
+/// DISCONNECT packet to plugins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client sent DISCONNECT. Mosquitto reports a session taken over by a new connection
+    /// with the same client id the same way, the two can't be told apart.
+    ClientDisconnect,
+    /// The connection closed or failed without a DISCONNECT
+    ConnectionLost,
+    /// Nothing was received from the client within its keepalive
+    KeepaliveTimeout,
+    ProtocolError,
+    MalformedPacket,
+    /// The client sent a packet larger than the broker accepts
+    OversizePacket,
+    NotAuthorized,
+    /// The client was kicked, by a plugin or the dynamic security plugin
+    AdministrativeAction,
+    OutOfMemory,
+    /// A code this crate doesn't map
+    Other(i32),
+}
+
+impl DisconnectReason {
+    /// Whether the client left on purpose, as opposed to a lost connection, an error or a kick
+    pub fn is_clean(&self) -> bool {
+        *self == DisconnectReason::ClientDisconnect
+    }
+}
+
+impl From<i32> for DisconnectReason {
+    fn from(reason: i32) -> DisconnectReason {
+        match reason {
+            0 => DisconnectReason::ClientDisconnect,
+            1 => DisconnectReason::OutOfMemory,
+            2 => DisconnectReason::ProtocolError,
+            7 => DisconnectReason::ConnectionLost,
+            11 => DisconnectReason::NotAuthorized,
+            19 => DisconnectReason::KeepaliveTimeout,
+            21 => DisconnectReason::MalformedPacket,
+            25 => DisconnectReason::OversizePacket,
+            30 => DisconnectReason::AdministrativeAction,
+            _ => DisconnectReason::Other(reason),
+        }
+    }
+}
+
+impl From<DisconnectReason> for i32 {
+    fn from(reason: DisconnectReason) -> i32 {
+        match reason {
+            DisconnectReason::ClientDisconnect => 0,
+            DisconnectReason::OutOfMemory => 1,
+            DisconnectReason::ProtocolError => 2,
+            DisconnectReason::ConnectionLost => 7,
+            DisconnectReason::NotAuthorized => 11,
+            DisconnectReason::KeepaliveTimeout => 19,
+            DisconnectReason::MalformedPacket => 21,
+            DisconnectReason::OversizePacket => 25,
+            DisconnectReason::AdministrativeAction => 30,
+            DisconnectReason::Other(reason) => reason,
+        }
+    }
+}
+
+/// The listener a client connected to.
+///
+/// The broker API tells the port, and the protocol through the client. The address the listener
+/// is bound to isn't available to plugins in mosquitto 2.0 or 2.1, plugins that need it can map
+/// the port to it, for example with their options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Listener {
+    /// The port, from mosquitto_client_port. Always None when building for mosquitto 2.0, whose
+    /// plugin API doesn't tell it.
+    pub port: Option<u16>,
+    /// Mqtt or Websockets, as the listener was configured
+    pub protocol: MosquittoClientProtocol,
 }
 
 pub trait MosquittoClientContext {
     /// Binding to mosquitto_client_address
-
+    ///
     /// NOTE: stored sessions might be disconnected upon a restart, and then the client being
-    /// disconnected will have no IP address, the address will then be of type None
+    /// disconnected will have no IP address, the address will then be of type None. It is None
+    /// as well when the address isn't an IP address, like for clients on unix sockets.
     fn get_address(&self) -> Option<std::net::IpAddr>;
     /// Binding to mosquitto_client_clean_session
     fn is_clean_session(&self) -> bool;
-    /// Binding to mosquitto_client_id
+    /// Binding to mosquitto_client_id, None as well if the id isn't valid UTF-8
     fn get_id(&self) -> Option<String>;
+    /// Binding to mosquitto_client_id, the id as the client sent it. Defaults to the bytes of
+    /// `get_id`, for implementations that only have UTF-8 ids.
+    fn get_id_bytes(&self) -> Option<Vec<u8>> {
+        self.get_id().map(String::into_bytes)
+    }
     /// Binding to mosquitto_client_keepalive
     fn get_keepalive(&self) -> i32;
-    /// Binding to mosquitto_client_certificate
-    fn get_certificate(&self) -> Option<&[u8]>;
-    // TODO replace with a reasonable return type from another lib. openssl or x509_parser maybe?
+    /// Binding to mosquitto_client_certificate, the certificate the client presented on a TLS
+    /// listener. With the `x509-parser` feature it can be parsed for its subject and SANs.
+    /// Always None on a broker built without TLS, OpenSSL is looked up at runtime.
+    fn get_certificate(&self) -> Option<Certificate>;
     /// Binding to mosquitto_client_protocol
     fn get_protocol(&self) -> MosquittoClientProtocol;
     /// Binding to mosquitto_client_protocol_version
     fn get_protocol_version(&self) -> MosquittoClientProtocolVersion;
     /// Binding to mosquitto_client_sub_count
     fn get_sub_count(&self) -> i32;
-    /// Binding to mosquitto_client_username
+    /// Binding to mosquitto_client_username, None as well if the username isn't valid UTF-8
     fn get_username(&self) -> Option<String>;
+    /// Binding to mosquitto_client_username, the username as the client sent it. Defaults to
+    /// the bytes of `get_username`, for implementations that only have UTF-8 usernames.
+    fn get_username_bytes(&self) -> Option<Vec<u8>> {
+        self.get_username().map(String::into_bytes)
+    }
     /// Binding to mosquitto_set_username
     /// Error is either NoMem or Inval
     fn set_username(&self, username: String) -> Result<Success, Error>;
+    /// The listener the client connected to, binding to mosquitto_client_port and
+    /// mosquitto_client_protocol
+    ///
+    /// NOTE: the mosquitto 2.0 plugin API has no mosquitto_client_port, when building for it the
+    /// listener only tells the protocol, and clients without a listener can't be told apart.
+    /// On 2.1 it is None for clients without a listener, like those of stored sessions after a
+    /// restart. Defaults to None, for implementations that don't know the listener.
+    fn get_listener(&self) -> Option<Listener> {
+        None
+    }
 }
 
 pub struct MosquittoClient {
@@ -331,8 +995,7 @@ impl MosquittoClientContext for MosquittoClient {
                 None
             } else {
                 let c_str = std::ffi::CStr::from_ptr(address);
-                let str = c_str.to_str().expect("Couldn't convert CStr to &str"); // TODO should we avoid expect here and instead return Option<String>?
-                Some(IpAddr::from_str(str).expect("Couldn't parse ip"))
+                IpAddr::from_str(c_str.to_str().ok()?).ok()
             }
         }
     }
@@ -346,6 +1009,10 @@ impl MosquittoClientContext for MosquittoClient {
     }
 
     fn get_id(&self) -> Option<String> {
+        String::from_utf8(self.get_id_bytes()?).ok()
+    }
+
+    fn get_id_bytes(&self) -> Option<Vec<u8>> {
         debug_assert!(!self.client.is_null(), "get_id: self client is null");
         unsafe {
             let client_id = mosquitto_client_id(self.client);
@@ -354,11 +1021,7 @@ impl MosquittoClientContext for MosquittoClient {
                 None
             } else {
                 let c_str = std::ffi::CStr::from_ptr(client_id);
-                let r_string = c_str
-                    .to_str()
-                    .expect("Couldn't convert CStr to &str")
-                    .to_string(); // TODO should we avoid expect here and instead return Option<String>?
-                Some(r_string)
+                Some(c_str.to_bytes().to_vec())
             }
         }
     }
@@ -368,31 +1031,17 @@ impl MosquittoClientContext for MosquittoClient {
         unsafe { mosquitto_client_keepalive(self.client) }
     }
 
-    fn get_certificate(&self) -> Option<&[u8]> {
-        unimplemented!()
+    fn get_certificate(&self) -> Option<Certificate> {
+        debug_assert!(
+            !self.client.is_null(),
+            "get_certificate: self client is null"
+        );
+        Certificate::from_client(self.client)
     }
 
     fn get_protocol(&self) -> MosquittoClientProtocol {
         debug_assert!(!self.client.is_null(), "get_protocol: self client is null");
-        unsafe {
-            let protocol = mosquitto_client_protocol(self.client) as u32;
-            if protocol == mosquitto_protocol_mp_mqtt {
-                MosquittoClientProtocol::Mqtt
-            } else if protocol == mosquitto_protocol_mp_mqttsn {
-                MosquittoClientProtocol::MqttSn
-            } else if protocol == mosquitto_protocol_mp_websockets {
-                MosquittoClientProtocol::Websockets
-            } else {
-                // TODO either we panic here. Or we need to return a result/option
-                // The benefit of returning the result/option would be to let library-user-space
-                // gracefully shutdown. Which would be preferable.
-
-                panic!(
-                    "mosquitto_client_protocol returned invalid protocol {}",
-                    protocol
-                );
-            }
-        }
+        unsafe { mosquitto_client_protocol(self.client) }.into()
     }
 
     fn get_protocol_version(&self) -> MosquittoClientProtocolVersion {
@@ -400,18 +1049,7 @@ impl MosquittoClientContext for MosquittoClient {
             !self.client.is_null(),
             "get_protocol_version: self client is null"
         );
-        unsafe {
-            let protocol_version = mosquitto_client_protocol_version(self.client);
-            match protocol_version {
-                3 => MosquittoClientProtocolVersion::V3,
-                4 => MosquittoClientProtocolVersion::V4,
-                5 => MosquittoClientProtocolVersion::V5,
-                _ => panic!(
-                    "invalid mosquitto client protocol version returned from mosquitto_client_protocol_version. {}",
-                    protocol_version
-                ),
-            }
-        }
+        unsafe { mosquitto_client_protocol_version(self.client) }.into()
     }
 
     fn get_sub_count(&self) -> i32 {
@@ -420,6 +1058,10 @@ impl MosquittoClientContext for MosquittoClient {
     }
 
     fn get_username(&self) -> Option<String> {
+        String::from_utf8(self.get_username_bytes()?).ok()
+    }
+
+    fn get_username_bytes(&self) -> Option<Vec<u8>> {
         debug_assert!(!self.client.is_null(), "get_username: self client is null");
         unsafe {
             let username = mosquitto_client_username(self.client);
@@ -427,7 +1069,7 @@ impl MosquittoClientContext for MosquittoClient {
                 None
             } else {
                 let c_str = std::ffi::CStr::from_ptr(username);
-                c_str.to_str().ok().map(From::from)
+                Some(c_str.to_bytes().to_vec())
             }
         }
     }
@@ -435,7 +1077,7 @@ impl MosquittoClientContext for MosquittoClient {
     fn set_username(&self, username: String) -> Result<Success, Error> {
         debug_assert!(!self.client.is_null(), "set_username: self client is null");
         unsafe {
-            let c_string = &CString::new(username).expect("no cstring for u");
+            let c_string = &CString::new(username).map_err(|_| Error::Inval)?;
             let res = mosquitto_set_username(self.client, c_string.as_c_str().as_ptr());
             match res {
                 0 => Ok(Success),
@@ -445,9 +1087,30 @@ impl MosquittoClientContext for MosquittoClient {
             }
         }
     }
+
+    #[cfg(feature = "mosq-2-0")]
+    fn get_listener(&self) -> Option<Listener> {
+        Some(Listener {
+            port: None,
+            protocol: self.get_protocol(),
+        })
+    }
+
+    #[cfg(feature = "mosq-2-1")]
+    fn get_listener(&self) -> Option<Listener> {
+        debug_assert!(!self.client.is_null(), "get_listener: self client is null");
+        let port = unsafe { mosquitto_client_port(self.client) };
+        match u16::try_from(port) {
+            Ok(port) if port > 0 => Some(Listener {
+                port: Some(port),
+                protocol: self.get_protocol(),
+            }),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MosquittoPluginEvent {
     MosqEvtReload = 1,
     MosqEvtAclCheck = 2,
@@ -459,6 +1122,14 @@ pub enum MosquittoPluginEvent {
     MosqEvtPskKey = 8,
     MosqEvtTick = 9,
     MosqEvtDisconnect = 10,
+    #[cfg(feature = "mosq-2-1")]
+    MosqEvtConnect = 11,
+    #[cfg(feature = "mosq-2-1")]
+    MosqEvtSubscribe = 12,
+    #[cfg(feature = "mosq-2-1")]
+    MosqEvtUnsubscribe = 13,
+    #[cfg(feature = "mosq-2-1")]
+    MosqEvtClientOffline = 28,
     Unknown = -1,
 }
 
@@ -475,29 +1146,168 @@ impl From<MosquittoPluginEvent> for i32 {
             MosquittoPluginEvent::MosqEvtPskKey => 8,
             MosquittoPluginEvent::MosqEvtTick => 9,
             MosquittoPluginEvent::MosqEvtDisconnect => 10,
+            #[cfg(feature = "mosq-2-1")]
+            MosquittoPluginEvent::MosqEvtConnect => 11,
+            #[cfg(feature = "mosq-2-1")]
+            MosquittoPluginEvent::MosqEvtSubscribe => 12,
+            #[cfg(feature = "mosq-2-1")]
+            MosquittoPluginEvent::MosqEvtUnsubscribe => 13,
+            #[cfg(feature = "mosq-2-1")]
+            MosquittoPluginEvent::MosqEvtClientOffline => 28,
             MosquittoPluginEvent::Unknown => -1,
         }
     }
 }
 
+/// A set of plugin events, declaring which callbacks a plugin implements.
+///
+/// Sets are combined with `union` in const context, or with `|`:
+///
+/// ```
+/// use mosquitto_plugin::EventSet;
+///
+/// const EVENTS: EventSet = EventSet::BASIC_AUTH.union(EventSet::ACL_CHECK);
+/// assert_eq!(EVENTS, EventSet::BASIC_AUTH | EventSet::ACL_CHECK);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventSet(u32);
+
+impl EventSet {
+    pub const NONE: EventSet = EventSet(0);
+    pub const RELOAD: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtReload);
+    pub const ACL_CHECK: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtAclCheck);
+    pub const BASIC_AUTH: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtBasicAuth);
+    pub const EXT_AUTH_START: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtExtAuthStart);
+    pub const EXT_AUTH_CONTINUE: EventSet =
+        EventSet::of(MosquittoPluginEvent::MosqEvtExtAuthContinue);
+    /// Both extended authentication events
+    pub const EXT_AUTH: EventSet = EventSet::EXT_AUTH_START.union(EventSet::EXT_AUTH_CONTINUE);
+    pub const CONTROL: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtControl);
+    pub const MESSAGE: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtMessage);
+    pub const PSK_KEY: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtPskKey);
+    pub const TICK: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtTick);
+    pub const DISCONNECT: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtDisconnect);
+    #[cfg(feature = "mosq-2-1")]
+    pub const CONNECT: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtConnect);
+    #[cfg(feature = "mosq-2-1")]
+    pub const SUBSCRIBE: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtSubscribe);
+    #[cfg(feature = "mosq-2-1")]
+    pub const UNSUBSCRIBE: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtUnsubscribe);
+    #[cfg(feature = "mosq-2-1")]
+    pub const CLIENT_OFFLINE: EventSet = EventSet::of(MosquittoPluginEvent::MosqEvtClientOffline);
+
+    const ALL_2_0: EventSet = EventSet::RELOAD
+        .union(EventSet::ACL_CHECK)
+        .union(EventSet::BASIC_AUTH)
+        .union(EventSet::EXT_AUTH)
+        .union(EventSet::CONTROL)
+        .union(EventSet::MESSAGE)
+        .union(EventSet::PSK_KEY)
+        .union(EventSet::TICK)
+        .union(EventSet::DISCONNECT);
+
+    /// Every event of the plugin API the crate is built for
+    #[cfg(feature = "mosq-2-0")]
+    pub const ALL: EventSet = EventSet::ALL_2_0;
+    /// Every event of the plugin API the crate is built for
+    #[cfg(feature = "mosq-2-1")]
+    pub const ALL: EventSet = EventSet::ALL_2_0
+        .union(EventSet::CONNECT)
+        .union(EventSet::SUBSCRIBE)
+        .union(EventSet::UNSUBSCRIBE)
+        .union(EventSet::CLIENT_OFFLINE);
+
+    const fn of(event: MosquittoPluginEvent) -> EventSet {
+        EventSet(1 << event as u32)
+    }
+
+    pub const fn union(self, other: EventSet) -> EventSet {
+        EventSet(self.0 | other.0)
+    }
+
+    pub const fn difference(self, other: EventSet) -> EventSet {
+        EventSet(self.0 & !other.0)
+    }
+
+    pub const fn contains(self, event: MosquittoPluginEvent) -> bool {
+        match event {
+            MosquittoPluginEvent::Unknown => false,
+            event => self.0 & EventSet::of(event).0 != 0,
+        }
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for EventSet {
+    type Output = EventSet;
+
+    fn bitor(self, other: EventSet) -> EventSet {
+        self.union(other)
+    }
+}
+
 pub trait MosquittoPlugin {
     /// This will be run once on every startup, or load, and will allocate the structure, to be
     /// reconstructed in other calls to the plugin.
     ///
     /// This requires unsafe usage due to nature of C calls
-    fn init(opts: MosquittoOpt) -> Self;
+    ///
+    /// An error is logged and returned to mosquitto, which refuses to start.
+    fn init(opts: MosquittoOpt) -> Result<Self, InitError>
+    where
+        Self: Sized;
+
+    /// What the plugin callbacks return to mosquitto when any of the functions below panics.
+    const PANIC_POLICY: PanicPolicy = PanicPolicy::Deny;
+
+    /// The events the plugin handles, only their callbacks are registered with mosquitto.
+    ///
+    /// Registering for an event has a cost even with the default implementation: message and
+    /// tick callbacks run on hot paths, and a plugin registered for basic auth or ACL checks takes
+    /// part in those decisions, which changes how mosquitto chains the other plugins. Defaults to
+    /// all events, plugins should narrow it down to the callbacks they implement.
+    const EVENTS: EventSet = EventSet::ALL;
+
+    /// The topics `on_control` handles, each registered separately with mosquitto while
+    /// `EVENTS` contains `EventSet::CONTROL`. Mosquitto only accepts topics below `$CONTROL/`,
+    /// versioned like `$CONTROL/our-plugin/v1`.
+    const CONTROL_TOPICS: &'static [&'static str] = &[];
+
+    /// How reload events are handled, in place with `on_reload` by default
+    const RELOAD_POLICY: ReloadPolicy = ReloadPolicy::InPlace;
 
-    /// Called when SIGHUP is sent to the broker PID
+    /// Called when SIGHUP is sent to the broker PID, with ReloadPolicy::InPlace
     #[allow(unused)]
     fn on_reload(&mut self, opts: MosquittoOpt) {}
 
+    /// Builds the instance that replaces this one on reload, with ReloadPolicy::Rebuild. The
+    /// replaced instance is dropped without `cleanup`. Defaults to `init` with the new options,
+    /// override it to carry state over to the new instance.
+    fn rebuild(&self, opts: MosquittoOpt) -> Result<Self, InitError>
+    where
+        Self: Sized,
+    {
+        Self::init(opts)
+    }
+
+    /// Called once when the plugin is unloaded, after its callbacks were unregistered and before
+    /// it is dropped, to flush state. `opts` are the options the plugin was loaded with.
+    #[allow(unused)]
+    fn cleanup(&mut self, opts: MosquittoOpt) {}
+
     /// Access level checks, default implementation always returns success
     /// If all acl checks from all plugins returns defer the action should be allowed.
     /// However that doesn't happen right now, if this returns Err(PluginDefer) for a write the message is not let through.
+    /// For policies that differ between listeners, `listener` is the one the client connected
+    /// to, as `client.get_listener()` tells it.
     #[allow(unused)]
     fn acl_check(
         &mut self,
         client: &dyn MosquittoClientContext,
+        listener: Option<Listener>,
         acl: AclCheckAccessLevel,
         msg: MosquittoMessage,
     ) -> Result<Success, Error> {
@@ -505,69 +1315,149 @@ pub trait MosquittoPlugin {
     }
     #[allow(unused)]
     /// Username and password checks, default implementation always returns success
+    /// `listener` is the one the client connected to, as for `acl_check`.
+    /// Username and password are passed as mosquitto got them, which isn't necessarily UTF-8,
+    /// the password is binary data in MQTT. Use `std::str::from_utf8` where text is expected.
     fn username_password(
         &mut self,
         client: &dyn MosquittoClientContext,
-        username: Option<&str>,
-        password: Option<&str>,
+        listener: Option<Listener>,
+        username: Option<&[u8]>,
+        password: Option<&[u8]>,
     ) -> Result<Success, Error> {
         Ok(Success)
     }
 
-    /// Authentication start. Default implementation always returns success. Return Err(Error::AuthContinue(_))
-    /// to send auth data to the client.
+    /// Extended authentication start, on connect and on re-authentication. Return
+    /// `AuthOutcome::Continue` to send auth data to the client. Default implementation defers.
+    /// Auth data is limited to 65535 bytes, more denies the client. `listener` is the one the
+    /// client connected to, as for `acl_check`.
+    #[allow(unused)]
     fn on_auth_start(
         &mut self,
-        _client: &dyn MosquittoClientContext,
-        _method: Option<&str>,
-        _data: Option<&[u8]>,
-    ) -> Result<Success, Error> {
-        Ok(Success)
+        client: &dyn MosquittoClientContext,
+        listener: Option<Listener>,
+        auth: AuthStart,
+    ) -> AuthOutcome {
+        AuthOutcome::Defer
     }
 
-    /// Authentication continue. Default implementation always returns success. Return `Err(Error::AuthContinue(_))`
-    /// to send auth data to the client or `Ok(Success)`.
+    /// Extended authentication continue, with the data of the next AUTH packet of the client.
+    /// Default implementation defers.
+    #[allow(unused)]
     fn on_auth_continue(
         &mut self,
-        _client: &dyn MosquittoClientContext,
-        _method: Option<&str>,
-        _data: Option<&[u8]>,
-    ) -> Result<Success, Error> {
-        Ok(Success)
+        client: &dyn MosquittoClientContext,
+        listener: Option<Listener>,
+        method: Option<&str>,
+        data: Option<&[u8]>,
+    ) -> AuthOutcome {
+        AuthOutcome::Defer
     }
 
-    /// Tested unsuccessfully. Haven't gotten this to work yet.
-    /// Suspect it has something to do with how the mosquitto_callback_register is called with the event_data parameter
+    /// Called for a message on one of the `CONTROL_TOPICS`. Control messages are not delivered
+    /// to subscribers, a returned response is sent to the client on `<topic>/response`.
     #[allow(unused)]
-    fn on_control(&mut self, client: &dyn MosquittoClientContext, message: MosquittoMessage) {}
+    fn on_control(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        message: MosquittoMessage,
+    ) -> Option<Vec<u8>> {
+        None
+    }
 
     /// Called when a message is sent on the broker.
     /// The message has to pass the ACL check otherwise this callback will not be called.
+    /// The returned action decides whether the message is delivered as is, replaced or dropped.
+    /// Default implementation always passes the message on.
     #[allow(unused)]
-    fn on_message(&mut self, client: &dyn MosquittoClientContext, message: MosquittoMessage) {}
+    fn on_message(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        message: MosquittoMessage,
+    ) -> MessageAction {
+        MessageAction::Pass
+    }
 
-    /// Untested
+    /// The pre-shared key for `identity` on a TLS-PSK listener, None defers to other plugins and
+    /// the psk_file of the listener. A key longer than mosquitto allows, half its max_key_len in
+    /// bytes, is logged and fails the handshake.
+    /// The hint and identity are passed on as the broker got them, `to_str` converts them.
     #[allow(unused)]
     fn on_psk(
         &mut self,
         client: &dyn MosquittoClientContext,
-        hint: &str,
-        identity: &str,
-        key: &str,
-        max_key_len: i32,
-    ) -> i32 {
-        0
+        hint: &std::ffi::CStr,
+        identity: &std::ffi::CStr,
+    ) -> Option<PskKey> {
+        None
     }
 
-    /// Called every 100 ms
-    /// All now_ns, next_ns, now_s, next_s parameters are always zero right now.
-    /// I'm not sure if it's a bug on this library's part or of mosquitto.
-    /// If you want to keep time you'll have to measure it yourself right now.
+    /// Called every 100 ms, after the due tasks of the scheduler ran.
+    /// now_s and now_ns are the seconds and nanoseconds of the broker's monotonic clock. Mosquitto
+    /// 2.0 passes zero for all of them, then now is read from CLOCK_MONOTONIC, the clock the broker
+    /// uses itself (off unix a clock of the plugin), and next_s and next_ns stay zero.
     #[allow(unused)]
     fn on_tick(&mut self, now_ns: i64, next_ns: i64, now_s: i32, next_s: i32) {}
 
+    /// The scheduler whose tasks run on the tick event, see `scheduler::Scheduler`. The plugin
+    /// has to register for `EventSet::TICK` for them to run.
+    fn scheduler(&mut self) -> Option<&mut scheduler::Scheduler<Self>>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Called when a client disconnects, `reason.is_clean()` tells a client that left from one
+    /// whose connection failed
+    #[allow(unused)]
+    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, reason: DisconnectReason) {}
+
+    /// Plugin name reported to the broker through mosquitto_plugin_set_info, mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    const PLUGIN_NAME: Option<&'static str> = None;
+
+    /// Plugin version reported along with `PLUGIN_NAME`, mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    const PLUGIN_VERSION: Option<&'static str> = None;
+
+    /// Called when a client has connected and was authenticated, mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    #[allow(unused)]
+    fn on_connect(&mut self, client: &dyn MosquittoClientContext) {}
+
+    /// Called when a client subscribes, after the ACL check of the subscription.
+    /// An error denies the subscription. Default implementation always returns success.
+    /// mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    #[allow(unused)]
+    fn on_subscribe(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        subscription: Subscription,
+    ) -> Result<Success, Error> {
+        Ok(Success)
+    }
+
+    /// Called when a client unsubscribes. An error keeps the subscription.
+    /// Default implementation always returns success. mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
     #[allow(unused)]
-    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, reason: i32) {}
+    fn on_unsubscribe(
+        &mut self,
+        client: &dyn MosquittoClientContext,
+        subscription: Subscription,
+    ) -> Result<Success, Error> {
+        Ok(Success)
+    }
+
+    /// Called when the network connection of a client with a persistent session is closed,
+    /// the session lives on. mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    #[allow(unused)]
+    fn on_client_offline(&mut self, client: &dyn MosquittoClientContext, reason: DisconnectReason) {
+    }
 }
 
 // #[derive(Debug)]
@@ -635,8 +1525,97 @@ pub trait MosquittoPlugin {
 
 #[cfg(test)]
 mod tests {
+    use crate::*;
+
+    // The mock broker links against the exports of a plugin, the unit tests don't load it
+    #[derive(Debug)]
+    struct Plugin;
+
+    impl MosquittoPlugin for Plugin {
+        fn init(_opts: MosquittoOpt) -> Result<Self, InitError> {
+            Ok(Plugin)
+        }
+    }
+
+    crate::create_dynamic_library!(Plugin);
+
     #[test]
     fn it_works() {
         debug_assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn event_sets() {
+        use super::{EventSet, MosquittoPluginEvent};
+
+        let auth = EventSet::BASIC_AUTH | EventSet::EXT_AUTH;
+        assert!(auth.contains(MosquittoPluginEvent::MosqEvtBasicAuth));
+        assert!(auth.contains(MosquittoPluginEvent::MosqEvtExtAuthContinue));
+        assert!(!auth.contains(MosquittoPluginEvent::MosqEvtAclCheck));
+        assert!(!EventSet::ALL.contains(MosquittoPluginEvent::Unknown));
+        assert!(EventSet::ALL.contains(MosquittoPluginEvent::MosqEvtDisconnect));
+        assert_eq!(auth.difference(EventSet::EXT_AUTH), EventSet::BASIC_AUTH);
+        assert!(EventSet::NONE.is_empty());
+    }
+
+    #[test]
+    fn maps_disconnect_reasons() {
+        use super::DisconnectReason;
+
+        assert_eq!(
+            DisconnectReason::from(0),
+            DisconnectReason::ClientDisconnect
+        );
+        assert_eq!(
+            DisconnectReason::from(19),
+            DisconnectReason::KeepaliveTimeout
+        );
+        assert_eq!(
+            DisconnectReason::from(30),
+            DisconnectReason::AdministrativeAction
+        );
+        assert_eq!(DisconnectReason::from(99), DisconnectReason::Other(99));
+        for code in -5..40 {
+            assert_eq!(i32::from(DisconnectReason::from(code)), code);
+        }
+        assert!(DisconnectReason::ClientDisconnect.is_clean());
+        assert!(!DisconnectReason::ConnectionLost.is_clean());
+    }
+
+    #[test]
+    fn falls_back_to_the_monotonic_clock_for_ticks() {
+        use std::time::Duration;
+
+        assert_eq!(super::__tick_time(5, 10), Duration::new(5, 10));
+        let before = super::scheduler::monotonic_now();
+        let now = super::__tick_time(0, 0);
+        assert!(now >= before && now > Duration::ZERO);
+    }
+
+    #[test]
+    fn refuses_options_that_are_not_utf8() {
+        use std::ffi::CString;
+
+        let strings: Vec<CString> = [&b"topic"[..], b"sensors", b"name", b"caf\xe9"]
+            .iter()
+            .map(|s| CString::new(*s).unwrap())
+            .collect();
+        let mut opts: Vec<mosquitto_opt> = strings
+            .chunks(2)
+            .map(|pair| mosquitto_opt {
+                key: pair[0].as_ptr() as *mut _,
+                value: pair[1].as_ptr() as *mut _,
+            })
+            .collect();
+
+        let valid = __from_ptr_and_size(opts.as_mut_ptr(), 1).unwrap();
+        assert_eq!(valid.get("topic"), Some(&"sensors"));
+        let e = __from_ptr_and_size(opts.as_mut_ptr(), 2).unwrap_err();
+        assert_eq!(e.error, Error::Inval);
+        assert_eq!(e.message, "option name is not valid UTF-8");
+
+        assert!(__from_ptr_and_size(std::ptr::null_mut(), 3)
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -1,12 +1,14 @@
 use crate::mosquitto_dev::{
-    mosquitto_broker_publish, mosquitto_kick_client_by_clientid, mosquitto_kick_client_by_username,
-    mosquitto_log_printf, mosquitto_property,
+    mosquitto_broker_publish_copy, mosquitto_kick_client_by_clientid,
+    mosquitto_kick_client_by_username, mosquitto_log_printf,
 };
+use crate::properties::{OwnedProperties, PropertyList};
 use crate::Error;
 use crate::{Success, QOS};
 use libc::c_void;
-use std::ffi::CString;
-use std::os::raw::c_char;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
 use std::ptr::null;
 
 /// Broadcast a message from the broker
@@ -18,43 +20,11 @@ pub fn publish_broadcast(
     qos: QOS,
     retain: bool,
 ) -> Result<Success, Error> {
-    let cstr = &CString::new(topic).expect("no cstring for u");
-    let bytes = cstr.as_bytes_with_nul();
-    let topic = bytes.as_ptr();
-
-    let nullptr: *const c_void = null();
-    let properties: *mut mosquitto_property = std::ptr::null_mut();
-
-    // let payload: *mut c_void = std::ptr::null_mut(); // payload bytes, non-null if payload length > 0, must be heap allocated
-    let payload_len = payload.len();
-    let payload: *const c_void = payload.as_ptr() as *const c_void; // payload bytes, non-null if payload length > 0, must be heap allocated
-
-    unsafe {
-        let c_payload: *mut c_void = libc::malloc(std::mem::size_of_val(&payload));
-        payload.copy_to(c_payload, payload_len);
-        /*
-         * https://mosquitto.org/api2/files/mosquitto_broker-h.html#mosquitto_broker_publish
-         * maybe want to switch to mosquitto_broker_publish to maintain ownership over
-         * payload memory.
-         * payload: payload bytes.  If payloadlen > 0 this must not be NULL.  Must be allocated on the heap.  Will be freed by mosquitto after use if the function returns success."
-         * What happens if it is not successfull? Do i need to free the memory myself? This is a leak if if i dont' free memory  in all cases except 0 (Success) below?
-         */
-        let res = mosquitto_broker_publish(
-            nullptr as *const c_char, // client id to send to, null = all clients
-            topic as *const c_char,
-            payload_len as i32, // payload length in bytes, 0 for empty payload
-            c_payload, // payload bytes, non-null if payload length > 0, must be heap allocated
-            qos.to_i32(), // qos
-            retain,    // retain
-            properties, //mqtt5 properties
-        );
-        match res {
-            0 => Ok(Success),
-            1 => Err(Error::NoMem),
-            3 => Err(Error::Inval),
-            _default => Err(Error::Unknown),
-        }
-    }
+    Publish::new(topic)
+        .payload(payload)
+        .qos(qos)
+        .retain(retain)
+        .send()
 }
 
 /// To be called from implementations of the plugin when
@@ -66,36 +36,163 @@ pub fn publish_to_client(
     qos: QOS,
     retain: bool,
 ) -> Result<Success, Error> {
-    let cstr = &CString::new(client_id).expect("no cstring for u");
-    let bytes = cstr.as_bytes_with_nul();
-    let client_id = bytes.as_ptr();
+    Publish::new(topic)
+        .payload(payload)
+        .qos(qos)
+        .retain(retain)
+        .to_client(client_id)
+        .send()
+}
+
+/// A message published by the broker, optionally carrying MQTT v5 properties.
+///
+/// Properties are only delivered to clients connected with MQTT v5, others get the plain message.
+///
+/// # Examples
+///
+/// ```no_run
+/// use mosquitto_plugin::mosquitto_calls::Publish;
+/// use mosquitto_plugin::QOS;
+///
+/// # fn main() -> Result<(), mosquitto_plugin::Error> {
+/// Publish::new("devices/sensor-1/response")
+///     .payload("{\"accepted\": true}")
+///     .qos(QOS::AtLeastOnce)
+///     .to_client("sensor-1")
+///     .content_type("application/json")
+///     .correlation_data("request-42")
+///     .user_property("handled-by", "broker")
+///     .send()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Publish {
+    topic: String,
+    payload: Vec<u8>,
+    qos: QOS,
+    retain: bool,
+    client_id: Option<String>,
+    properties: OwnedProperties,
+}
 
-    let cstr = &CString::new(topic).expect("no cstring for u");
-    let bytes = cstr.as_bytes_with_nul();
-    let topic = bytes.as_ptr();
+impl Publish {
+    /// An empty, non retained QoS 0 message on `topic`, sent to all subscribed clients
+    pub fn new(topic: impl Into<String>) -> Publish {
+        Publish {
+            topic: topic.into(),
+            payload: Vec::new(),
+            qos: QOS::AtMostOnce,
+            retain: false,
+            client_id: None,
+            properties: OwnedProperties::default(),
+        }
+    }
 
-    let payload_len = payload.len();
-    let payload: *const c_void = payload.as_ptr() as *const c_void;
+    pub fn payload(mut self, payload: impl AsRef<[u8]>) -> Publish {
+        self.payload = payload.as_ref().to_vec();
+        self
+    }
 
-    unsafe {
-        let c_payload: *mut c_void = libc::malloc(std::mem::size_of_val(&payload));
-        payload.copy_to(c_payload, payload_len);
-
-        let res = mosquitto_broker_publish(
-            client_id as *const c_char, // client id to send to, null = all clients
-            topic as *const c_char,     // topic to publish on
-            payload_len as i32,         // payload length in bytes, 0 for empty payload
-            c_payload, // payload bytes, non-null if payload length > 0, must be heap allocated
-            qos.to_i32(), // qos
-            retain,    // retain
-            std::ptr::null_mut(), //mqtt5 properties
-        );
-        match res {
-            0 => Ok(Success),
-            1 => Err(Error::NoMem),
-            3 => Err(Error::Inval),
-            _default => Err(Error::Unknown),
+    pub fn qos(mut self, qos: QOS) -> Publish {
+        self.qos = qos;
+        self
+    }
+
+    pub fn retain(mut self, retain: bool) -> Publish {
+        self.retain = retain;
+        self
+    }
+
+    /// Only deliver the message to the client with id `client_id`, if it is subscribed to the topic
+    pub fn to_client(mut self, client_id: impl Into<String>) -> Publish {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    /// Adds a user property. Can be called several times, also with the same name.
+    pub fn user_property(mut self, name: impl Into<String>, value: impl Into<String>) -> Publish {
+        self.properties
+            .user_properties
+            .push((name.into(), value.into()));
+        self
+    }
+
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Publish {
+        self.properties.content_type = Some(content_type.into());
+        self
+    }
+
+    /// Topic the receiver should publish its response on
+    pub fn response_topic(mut self, response_topic: impl Into<String>) -> Publish {
+        self.properties.response_topic = Some(response_topic.into());
+        self
+    }
+
+    /// Data identifying the request a response belongs to, at most 65535 bytes
+    pub fn correlation_data(mut self, correlation_data: impl AsRef<[u8]>) -> Publish {
+        self.properties.correlation_data = Some(correlation_data.as_ref().to_vec());
+        self
+    }
+
+    /// Seconds after which the broker discards the message if it hasn't been delivered
+    pub fn message_expiry_interval(mut self, seconds: u32) -> Publish {
+        self.properties.message_expiry_interval = Some(seconds);
+        self
+    }
+
+    /// Hands the message to the broker for delivery.
+    /// Error is Inval if a string contains a nul byte or a value is out of range
+    pub fn send(self) -> Result<Success, Error> {
+        let topic = CString::new(self.topic.as_str()).map_err(|_| Error::Inval)?;
+        let client_id = match &self.client_id {
+            Some(client_id) => Some(CString::new(client_id.as_str()).map_err(|_| Error::Inval)?),
+            None => None,
+        };
+        let properties = self.properties.to_list()?;
+
+        broker_publish(
+            client_id.as_deref(),
+            &topic,
+            &self.payload,
+            self.qos,
+            self.retain,
+            properties,
+        )
+    }
+}
+
+// Publishes through mosquitto_broker_publish_copy, which copies the payload into broker owned
+// memory, so the payload stays ours whatever the outcome. The properties are taken over by the
+// broker only when publishing succeeds, otherwise they are freed when `properties` is dropped.
+fn broker_publish(
+    client_id: Option<&CStr>,
+    topic: &CStr,
+    payload: &[u8],
+    qos: QOS,
+    retain: bool,
+    mut properties: PropertyList,
+) -> Result<Success, Error> {
+    let client_id = client_id.map_or(null(), |client_id| client_id.as_ptr());
+    let payload_len = c_int::try_from(payload.len()).map_err(|_| Error::PayloadSize)?;
+
+    let res = unsafe {
+        mosquitto_broker_publish_copy(
+            client_id,                         // client id to send to, null = all clients
+            topic.as_ptr(),                    // topic to publish on
+            payload_len,                       // payload length in bytes, 0 for empty payload
+            payload.as_ptr() as *const c_void, // payload bytes, copied by the broker
+            qos.to_i32(),                      // qos
+            retain,                            // retain
+            properties.as_mut_ptr(),           // mqtt5 properties
+        )
+    };
+    match res {
+        0 => {
+            properties.forget();
+            Ok(Success)
         }
+        error => Err(Error::from(error)),
     }
 }
 
@@ -259,3 +356,59 @@ macro_rules! mosquitto_error {
     // mosquitto_error!("a {} event", "log")
     ($($arg:tt)+) => ($crate::mosquitto_calls::mosquitto_log($crate::mosquitto_calls::LogLevel::Err, &format!($($arg)+)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockBroker;
+
+    #[test]
+    fn publishes_whole_payload() {
+        let mut broker = MockBroker::new();
+        let long = (0..=255).cycle().take(4096).collect::<Vec<u8>>();
+        publish_broadcast("empty", b"", QOS::AtMostOnce, false).unwrap();
+        publish_broadcast("nine", b"123456789", QOS::AtMostOnce, false).unwrap();
+        publish_to_client("client", "long", &long, QOS::AtLeastOnce, true).unwrap();
+
+        let published = broker
+            .take_published()
+            .into_iter()
+            .map(|m| (m.client_id, m.topic, m.payload))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            published,
+            vec![
+                (None, "empty".to_string(), Vec::new()),
+                (None, "nine".to_string(), b"123456789".to_vec()),
+                (Some("client".to_string()), "long".to_string(), long),
+            ]
+        );
+    }
+
+    #[test]
+    fn publishes_properties() {
+        let mut broker = MockBroker::new();
+        Publish::new("request")
+            .payload(b"ping")
+            .qos(QOS::ExactlyOnce)
+            .user_property("origin", "plugin")
+            .content_type("text/plain")
+            .response_topic("reply")
+            .correlation_data(b"42")
+            .message_expiry_interval(30)
+            .send()
+            .unwrap();
+
+        let published = broker.take_published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].qos, 2);
+        assert_eq!(
+            published[0].user_properties,
+            vec![("origin".to_string(), "plugin".to_string())]
+        );
+        assert_eq!(published[0].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(published[0].response_topic.as_deref(), Some("reply"));
+        assert_eq!(published[0].correlation_data.as_deref(), Some(&b"42"[..]));
+        assert_eq!(published[0].message_expiry_interval, Some(30));
+    }
+}
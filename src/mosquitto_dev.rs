@@ -3,5 +3,20 @@
 #![allow(non_snake_case)]
 #![allow(unused)]
 
-// All the bindings provided by mosquitto_plugin.h
+// All the bindings provided by mosquitto_plugin.h, generated from the installed headers
+#[cfg(feature = "bindgen")]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+// The same bindings, pre-generated for the plugin API selected by the mosq-2-* features
+#[cfg(all(feature = "vendored", not(feature = "bindgen"), feature = "mosq-2-0"))]
+include!("bindings/mosquitto_2_0.rs");
+#[cfg(all(
+    feature = "vendored",
+    not(feature = "bindgen"),
+    feature = "mosq-2-1",
+    not(feature = "mosq-2-0")
+))]
+include!("bindings/mosquitto_2_1.rs");
+
+#[cfg(not(any(feature = "vendored", feature = "bindgen")))]
+compile_error!("either the `vendored` or the `bindgen` feature has to be enabled");
@@ -0,0 +1,673 @@
+// Typed plugin options with serde.
+//
+// Mosquitto hands the `plugin_opt_*` lines to the plugin as plain strings, with the prefix
+// stripped. The deserializer below parses them into the fields of a struct instead: numbers and
+// bools are parsed from the string and lists are comma separated. Fields using the `duration`
+// helper are parsed from a number with a unit.
+// Underscores in the key nest structs, `plugin_opt_db_host` is the `host` field of the `db`
+// field.
+
+use crate::MosquittoOpt;
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use std::fmt;
+use std::time::Duration;
+
+/// Deserializes the plugin options into `T`.
+///
+/// ```
+/// # use mosquitto_plugin::MosquittoOpt;
+/// use serde::Deserialize;
+/// use std::time::Duration;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     topics: Vec<String>,
+///     #[serde(with = "mosquitto_plugin::options::duration")]
+///     timeout: Duration,
+///     db: Db,
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct Db {
+///     host: String,
+///     port: Option<u16>,
+/// }
+///
+/// // plugin_opt_topics a/#,b/#
+/// // plugin_opt_timeout 30s
+/// // plugin_opt_db_host localhost
+/// let opts: MosquittoOpt = [("topics", "a/#,b/#"), ("timeout", "30s"), ("db_host", "localhost")]
+///     .iter()
+///     .copied()
+///     .collect();
+/// let config: Config = mosquitto_plugin::options::from_opts(&opts).unwrap();
+/// assert_eq!(config.topics, ["a/#", "b/#"]);
+/// assert_eq!(config.timeout, Duration::from_secs(30));
+/// assert_eq!(config.db.host, "localhost");
+/// assert_eq!(config.db.port, None);
+/// ```
+///
+/// Values are parsed as follows:
+/// - numbers and chars with `str::parse`, bools from `true` or `false`
+/// - sequences are split at commas, surrounding whitespace is trimmed, `""` is an empty list
+/// - `std::time::Duration` fields with `#[serde(with = "mosquitto_plugin::options::duration")]`
+///   are a number with one of the units `ms`, `s`, `m`, `h` or `d`, a number without a unit is
+///   seconds
+/// - enums name a unit variant
+/// - structs and maps take the options prefixed with the field name and an underscore
+///
+/// Options that don't match any field are ignored, missing `Option` fields are `None`. The error
+/// converts into an `InitError`, so `init` can return it with `?`.
+pub fn from_opts<'a, T: Deserialize<'a>>(opts: &MosquittoOpt<'a>) -> Result<T, OptionsError> {
+    T::deserialize(Section {
+        opts,
+        prefix: String::new(),
+    })
+}
+
+/// Why the options couldn't be deserialized, naming the offending option
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionsError {
+    key: Option<String>,
+    kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ErrorKind {
+    // Raised by the visitor of a struct, the section adds its prefix to the field
+    MissingField(&'static str),
+    Missing,
+    Invalid(String),
+}
+
+impl OptionsError {
+    /// The key of the option, without the `plugin_opt_` prefix
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    fn invalid(key: &str, message: impl fmt::Display) -> OptionsError {
+        OptionsError {
+            key: Some(key.to_string()),
+            kind: ErrorKind::Invalid(message.to_string()),
+        }
+    }
+
+    fn at(mut self, key: &str) -> OptionsError {
+        if self.key.is_none() {
+            self.key = Some(key.to_string());
+        }
+        self
+    }
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.key, &self.kind) {
+            (_, ErrorKind::MissingField(field)) => write!(f, "missing option {}", field),
+            (Some(key), ErrorKind::Missing) => write!(f, "missing option {}", key),
+            (Some(key), ErrorKind::Invalid(message)) => write!(f, "option {}: {}", key, message),
+            (None, ErrorKind::Invalid(message)) => write!(f, "{}", message),
+            (None, ErrorKind::Missing) => write!(f, "missing option"),
+        }
+    }
+}
+
+impl std::error::Error for OptionsError {}
+
+impl de::Error for OptionsError {
+    fn custom<T: fmt::Display>(message: T) -> OptionsError {
+        OptionsError {
+            key: None,
+            kind: ErrorKind::Invalid(message.to_string()),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> OptionsError {
+        OptionsError {
+            key: None,
+            kind: ErrorKind::MissingField(field),
+        }
+    }
+}
+
+// The options below a prefix, deserialized as a struct or map
+struct Section<'a, 'b> {
+    opts: &'b MosquittoOpt<'a>,
+    prefix: String,
+}
+
+impl<'a, 'b> Deserializer<'a> for Section<'a, 'b> {
+    type Error = OptionsError;
+
+    fn deserialize_any<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        let mut entries: Vec<_> = self
+            .opts
+            .iter()
+            .filter_map(|(key, value)| {
+                let field = key.strip_prefix(self.prefix.as_str())?;
+                Some((field.to_string(), self.entry(key.to_string(), Some(*value))))
+            })
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        visitor.visit_map(Entries {
+            entries: entries.into_iter(),
+            next: None,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        let entries: Vec<_> = fields
+            .iter()
+            .filter_map(|field| {
+                let key = format!("{}{}", self.prefix, field);
+                let value = self.opts.get(key.as_str()).copied();
+                let nested = format!("{}_", key);
+                if value.is_some() || self.opts.keys().any(|k| k.starts_with(&nested)) {
+                    Some((field.to_string(), self.entry(key, value)))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let prefix = self.prefix;
+        visitor
+            .visit_map(Entries {
+                entries: entries.into_iter(),
+                next: None,
+            })
+            .map_err(|e| match e.kind {
+                ErrorKind::MissingField(field) => OptionsError {
+                    key: Some(format!("{}{}", prefix, field)),
+                    kind: ErrorKind::Missing,
+                },
+                _ => e,
+            })
+    }
+
+    fn deserialize_option<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        <W: Visitor<'a>>
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct enum identifier ignored_any
+    }
+}
+
+impl<'a, 'b> Section<'a, 'b> {
+    fn entry(&self, key: String, value: Option<&'a str>) -> Entry<'a, 'b> {
+        Entry {
+            opts: self.opts,
+            key,
+            value,
+        }
+    }
+}
+
+struct Entries<'a, 'b> {
+    entries: std::vec::IntoIter<(String, Entry<'a, 'b>)>,
+    next: Option<Entry<'a, 'b>>,
+}
+
+impl<'a, 'b> MapAccess<'a> for Entries<'a, 'b> {
+    type Error = OptionsError;
+
+    fn next_key_seed<K: DeserializeSeed<'a>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, OptionsError> {
+        match self.entries.next() {
+            Some((field, entry)) => {
+                self.next = Some(entry);
+                seed.deserialize(de::value::StringDeserializer::new(field))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'a>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, OptionsError> {
+        let entry = self
+            .next
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before its key"))?;
+        let key = entry.key.clone();
+        seed.deserialize(entry).map_err(|e| e.at(&key))
+    }
+}
+
+// A single option, or the options nested below it when it has no value of its own
+struct Entry<'a, 'b> {
+    opts: &'b MosquittoOpt<'a>,
+    key: String,
+    value: Option<&'a str>,
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+                let value = self.value()?;
+                match value.parse() {
+                    Ok(parsed) => visitor.$visit(parsed),
+                    Err(e) => Err(OptionsError::invalid(&self.key, format!("{:?}: {}", value, e))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'a, 'b> Entry<'a, 'b> {
+    fn value(&self) -> Result<&'a str, OptionsError> {
+        self.value.ok_or_else(|| OptionsError {
+            key: Some(self.key.clone()),
+            kind: ErrorKind::Missing,
+        })
+    }
+
+    fn section(self) -> Section<'a, 'b> {
+        Section {
+            opts: self.opts,
+            prefix: format!("{}_", self.key),
+        }
+    }
+}
+
+impl<'a, 'b> Deserializer<'a> for Entry<'a, 'b> {
+    type Error = OptionsError;
+
+    fn deserialize_any<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        match self.value {
+            Some(value) => visitor.visit_borrowed_str(value),
+            None => self.section().deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        match self.value()? {
+            "true" => visitor.visit_bool(true),
+            "false" => visitor.visit_bool(false),
+            other => Err(OptionsError::invalid(
+                &self.key,
+                format!("{:?}: expected true or false", other),
+            )),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        visitor.visit_borrowed_str(self.value()?)
+    }
+
+    fn deserialize_string<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        visitor.visit_borrowed_bytes(self.value()?.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        let value = self.value()?;
+        let items = if value.trim().is_empty() {
+            Vec::new()
+        } else {
+            value.split(',').map(str::trim).collect()
+        };
+        visitor.visit_seq(Items {
+            opts: self.opts,
+            key: &self.key,
+            items: items.into_iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'a>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        self.section().deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'a>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        match self.value {
+            Some(_) => Err(OptionsError::invalid(
+                &self.key,
+                format!("expected options prefixed with {}_", self.key),
+            )),
+            None => self.section().deserialize_struct(name, fields, visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, OptionsError> {
+        visitor.visit_enum(BorrowedStrDeserializer::new(self.value()?))
+    }
+
+    fn deserialize_identifier<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'a>>(self, visitor: V) -> Result<V::Value, OptionsError> {
+        visitor.visit_unit()
+    }
+}
+
+// The comma separated items of a list option
+struct Items<'a, 'b, 'c> {
+    opts: &'b MosquittoOpt<'a>,
+    key: &'c str,
+    items: std::vec::IntoIter<&'a str>,
+}
+
+impl<'a, 'b, 'c> SeqAccess<'a> for Items<'a, 'b, 'c> {
+    type Error = OptionsError;
+
+    fn next_element_seed<T: DeserializeSeed<'a>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, OptionsError> {
+        match self.items.next() {
+            Some(item) => seed
+                .deserialize(Entry {
+                    opts: self.opts,
+                    key: self.key.to_string(),
+                    value: Some(item),
+                })
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// Deserializes a `std::time::Duration` from an option like `30s`, for
+/// `#[serde(with = "mosquitto_plugin::options::duration")]`.
+///
+/// The units are `ms`, `s`, `m`, `h` and `d`, a number without a unit is seconds. Use
+/// `duration::option` for an `Option<Duration>`.
+pub mod duration {
+    use serde::de::{self, Deserialize, Deserializer};
+    use std::time::Duration;
+
+    pub fn deserialize<'a, D: Deserializer<'a>>(deserializer: D) -> Result<Duration, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        super::parse_duration(&value)
+            .ok_or_else(|| de::Error::custom(format!("{:?}: expected a duration like 30s", value)))
+    }
+
+    /// Deserializes an `Option<Duration>`, for
+    /// `#[serde(default, with = "mosquitto_plugin::options::duration::option")]`
+    pub mod option {
+        use serde::de::Deserializer;
+        use std::time::Duration;
+
+        pub fn deserialize<'a, D: Deserializer<'a>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            super::deserialize(deserializer).map(Some)
+        }
+    }
+}
+
+// "1500ms", "30s", "5m", "2h", "1d", or a number of seconds
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    match unit.trim() {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" => number.checked_mul(60).map(Duration::from_secs),
+        "h" => number.checked_mul(60 * 60).map(Duration::from_secs),
+        "d" => number.checked_mul(24 * 60 * 60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        topic: String,
+        retries: u8,
+        enabled: bool,
+        #[serde(default)]
+        verbose: bool,
+        #[serde(with = "duration")]
+        timeout: Duration,
+        #[serde(default, with = "duration::option")]
+        grace: Option<Duration>,
+        ports: Vec<u16>,
+        mode: Mode,
+        db: Db,
+        cache: Option<Db>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Mode {
+        Strict,
+        Permissive,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Db {
+        host: String,
+        port: Option<u16>,
+    }
+
+    fn opts<'a>(pairs: &[(&'a str, &'a str)]) -> MosquittoOpt<'a> {
+        pairs.iter().copied().collect()
+    }
+
+    const VALID: &[(&str, &str)] = &[
+        ("topic", "sensors/#"),
+        ("retries", "3"),
+        ("enabled", "true"),
+        ("timeout", "1500ms"),
+        ("ports", "1883, 8883"),
+        ("mode", "strict"),
+        ("db_host", "localhost"),
+        ("db_port", "5432"),
+        ("unrelated", "ignored"),
+    ];
+
+    #[test]
+    fn deserializes_typed_and_nested_options() {
+        let config: Config = from_opts(&opts(VALID)).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                topic: "sensors/#".to_string(),
+                retries: 3,
+                enabled: true,
+                verbose: false,
+                timeout: Duration::from_millis(1500),
+                grace: None,
+                ports: vec![1883, 8883],
+                mode: Mode::Strict,
+                db: Db {
+                    host: "localhost".to_string(),
+                    port: Some(5432),
+                },
+                cache: None,
+            }
+        );
+
+        let map: HashMap<String, String> = from_opts(&opts(&[("a", "1"), ("b_c", "2")])).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["b_c"], "2");
+    }
+
+    #[test]
+    fn deserializes_durations_with_the_helper() {
+        let mut pairs = opts(VALID);
+        pairs.insert("grace", "5m");
+        let config: Config = from_opts(&pairs).unwrap();
+        assert_eq!(config.grace, Some(Duration::from_secs(300)));
+
+        assert_eq!(
+            error_for("timeout", "soon").to_string(),
+            "option timeout: \"soon\": expected a duration like 30s"
+        );
+
+        // Without the helper a Duration is the struct serde makes of it
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Plain {
+            timeout: Duration,
+        }
+        let plain: Plain =
+            from_opts(&opts(&[("timeout_secs", "2"), ("timeout_nanos", "0")])).unwrap();
+        assert_eq!(plain.timeout, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("5 weeks"), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("-5s"), None);
+    }
+
+    fn error_for(key: &str, value: &str) -> OptionsError {
+        let mut pairs = opts(VALID);
+        pairs.insert(key, value);
+        from_opts::<Config>(&pairs).unwrap_err()
+    }
+
+    #[test]
+    fn names_the_offending_key() {
+        let e = error_for("retries", "many");
+        assert_eq!(e.key(), Some("retries"));
+        assert!(e.to_string().starts_with("option retries: \"many\""));
+
+        assert_eq!(error_for("retries", "300").key(), Some("retries"));
+        assert_eq!(error_for("enabled", "yes").key(), Some("enabled"));
+        assert_eq!(error_for("timeout", "soon").key(), Some("timeout"));
+        assert_eq!(error_for("ports", "1883,x").key(), Some("ports"));
+        assert_eq!(error_for("mode", "lenient").key(), Some("mode"));
+        assert_eq!(error_for("db_port", "-1").key(), Some("db_port"));
+        assert_eq!(
+            error_for("cache_port", "1").to_string(),
+            "missing option cache_host"
+        );
+
+        let mut pairs = opts(VALID);
+        pairs.remove("topic");
+        let e = from_opts::<Config>(&pairs).unwrap_err();
+        assert_eq!(e.key(), Some("topic"));
+        assert_eq!(e.to_string(), "missing option topic");
+
+        pairs = opts(VALID);
+        pairs.remove("db_host");
+        assert_eq!(
+            from_opts::<Config>(&pairs).unwrap_err().key(),
+            Some("db_host")
+        );
+    }
+}
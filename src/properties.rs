@@ -0,0 +1,417 @@
+// Safe read access to the MQTT v5 properties mosquitto attaches to events.
+//
+// The values are read with the mosquitto_property_read_* functions, which hand out copies
+// allocated by the broker. Those are converted into owned rust values and freed right away, so
+// nothing returned from here refers to broker memory.
+
+use crate::mosquitto_dev::*;
+use crate::{Error, Success};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr::null_mut;
+
+// Property identifiers as defined in mqtt_protocol.h, which is not covered by the bindings
+pub const MQTT_PROP_PAYLOAD_FORMAT_INDICATOR: i32 = 1;
+pub const MQTT_PROP_MESSAGE_EXPIRY_INTERVAL: i32 = 2;
+pub const MQTT_PROP_CONTENT_TYPE: i32 = 3;
+pub const MQTT_PROP_RESPONSE_TOPIC: i32 = 8;
+pub const MQTT_PROP_CORRELATION_DATA: i32 = 9;
+pub const MQTT_PROP_REASON_STRING: i32 = 31;
+pub const MQTT_PROP_USER_PROPERTY: i32 = 38;
+
+/// Borrowed view of the MQTT v5 property list of an event.
+/// Clients connected with MQTT v3.x never send properties, the view is empty for them.
+#[derive(Clone, Copy)]
+pub struct Properties<'a> {
+    list: *const mosquitto_property,
+    _event: PhantomData<&'a mosquitto_property>,
+}
+
+impl<'a> Properties<'a> {
+    /// Wraps a property list received from mosquitto. A null pointer is an empty list.
+    ///
+    /// # Safety
+    /// `list` has to be null or point to a property list that stays valid for `'a`.
+    pub unsafe fn from_ptr(list: *const mosquitto_property) -> Properties<'a> {
+        Properties {
+            list,
+            _event: PhantomData,
+        }
+    }
+
+    /// A view without any properties
+    pub fn empty() -> Properties<'a> {
+        Properties {
+            list: std::ptr::null(),
+            _event: PhantomData,
+        }
+    }
+
+    /// The raw property list, for use with the mosquitto_property_* functions directly
+    pub fn as_ptr(&self) -> *const mosquitto_property {
+        self.list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_null()
+    }
+
+    /// The content type property, describing the payload
+    pub fn content_type(&self) -> Option<String> {
+        self.read_string(MQTT_PROP_CONTENT_TYPE)
+    }
+
+    /// The response topic of a request/response exchange
+    pub fn response_topic(&self) -> Option<String> {
+        self.read_string(MQTT_PROP_RESPONSE_TOPIC)
+    }
+
+    /// The correlation data of a request/response exchange
+    pub fn correlation_data(&self) -> Option<Vec<u8>> {
+        if self.list.is_null() {
+            return None;
+        }
+        let mut value: *mut c_void = null_mut();
+        let mut len: u16 = 0;
+        let found = unsafe {
+            mosquitto_property_read_binary(
+                self.list,
+                MQTT_PROP_CORRELATION_DATA,
+                &mut value,
+                &mut len,
+                false,
+            )
+        };
+        if found.is_null() {
+            return None;
+        }
+        if value.is_null() {
+            return Some(Vec::new());
+        }
+        let data = unsafe { std::slice::from_raw_parts(value as *const u8, len as usize) }.to_vec();
+        unsafe { mosquitto_free(value) };
+        Some(data)
+    }
+
+    /// The message expiry interval in seconds
+    pub fn message_expiry_interval(&self) -> Option<u32> {
+        if self.list.is_null() {
+            return None;
+        }
+        let mut value: u32 = 0;
+        let found = unsafe {
+            mosquitto_property_read_int32(
+                self.list,
+                MQTT_PROP_MESSAGE_EXPIRY_INTERVAL,
+                &mut value,
+                false,
+            )
+        };
+        (!found.is_null()).then_some(value)
+    }
+
+    /// All user properties as name/value pairs, in the order they were sent.
+    /// Names may appear more than once.
+    pub fn user_properties(&self) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        let mut skip_first = false;
+        let mut list = self.list;
+        while !list.is_null() {
+            let mut name: *mut c_char = null_mut();
+            let mut value: *mut c_char = null_mut();
+            list = unsafe {
+                mosquitto_property_read_string_pair(
+                    list,
+                    MQTT_PROP_USER_PROPERTY,
+                    &mut name,
+                    &mut value,
+                    skip_first,
+                )
+            };
+            if !list.is_null() {
+                pairs.push((take_string(name), take_string(value)));
+            }
+            skip_first = true;
+        }
+        pairs
+    }
+
+    /// Copies the properties out of the event, e.g. to change them for a replaced message
+    pub fn into_owned(self) -> OwnedProperties {
+        OwnedProperties {
+            user_properties: self.user_properties(),
+            content_type: self.content_type(),
+            response_topic: self.response_topic(),
+            correlation_data: self.correlation_data(),
+            message_expiry_interval: self.message_expiry_interval(),
+        }
+    }
+
+    /// The value of the first user property called `name`
+    pub fn user_property(&self, name: &str) -> Option<String> {
+        self.user_properties()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+
+    fn read_string(&self, identifier: i32) -> Option<String> {
+        if self.list.is_null() {
+            return None;
+        }
+        let mut value: *mut c_char = null_mut();
+        let found =
+            unsafe { mosquitto_property_read_string(self.list, identifier, &mut value, false) };
+        (!found.is_null()).then(|| take_string(value))
+    }
+}
+
+impl<'a> fmt::Debug for Properties<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("Properties {}");
+        }
+        f.debug_struct("Properties")
+            .field("content_type", &self.content_type())
+            .field("response_topic", &self.response_topic())
+            .field("correlation_data", &self.correlation_data())
+            .field("message_expiry_interval", &self.message_expiry_interval())
+            .field("user_properties", &self.user_properties())
+            .finish()
+    }
+}
+
+/// The properties a plugin sets on a message it publishes or replaces, the ones [`Properties`]
+/// reads
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OwnedProperties {
+    /// Name/value pairs in the order they are sent, names may appear more than once
+    pub user_properties: Vec<(String, String)>,
+    pub content_type: Option<String>,
+    pub response_topic: Option<String>,
+    /// At most 65535 bytes
+    pub correlation_data: Option<Vec<u8>>,
+    /// Seconds after which the broker discards the message if it hasn't been delivered
+    pub message_expiry_interval: Option<u32>,
+}
+
+impl OwnedProperties {
+    // Builds the list the broker takes over from the plugin.
+    // Error is Inval if a string contains a nul byte or a value is out of range
+    pub(crate) fn to_list(&self) -> Result<PropertyList, Error> {
+        let mut properties = PropertyList::new();
+        for (name, value) in &self.user_properties {
+            let name = CString::new(name.as_str()).map_err(|_| Error::Inval)?;
+            let value = CString::new(value.as_str()).map_err(|_| Error::Inval)?;
+            properties.add(|list| unsafe {
+                mosquitto_property_add_string_pair(
+                    list,
+                    MQTT_PROP_USER_PROPERTY,
+                    name.as_ptr(),
+                    value.as_ptr(),
+                )
+            })?;
+        }
+        if let Some(content_type) = &self.content_type {
+            let content_type = CString::new(content_type.as_str()).map_err(|_| Error::Inval)?;
+            properties.add(|list| unsafe {
+                mosquitto_property_add_string(list, MQTT_PROP_CONTENT_TYPE, content_type.as_ptr())
+            })?;
+        }
+        if let Some(response_topic) = &self.response_topic {
+            let response_topic = CString::new(response_topic.as_str()).map_err(|_| Error::Inval)?;
+            properties.add(|list| unsafe {
+                mosquitto_property_add_string(
+                    list,
+                    MQTT_PROP_RESPONSE_TOPIC,
+                    response_topic.as_ptr(),
+                )
+            })?;
+        }
+        if let Some(correlation_data) = &self.correlation_data {
+            let len = u16::try_from(correlation_data.len()).map_err(|_| Error::Inval)?;
+            properties.add(|list| unsafe {
+                mosquitto_property_add_binary(
+                    list,
+                    MQTT_PROP_CORRELATION_DATA,
+                    correlation_data.as_ptr() as *const c_void,
+                    len,
+                )
+            })?;
+        }
+        if let Some(seconds) = self.message_expiry_interval {
+            properties.add(|list| unsafe {
+                mosquitto_property_add_int32(list, MQTT_PROP_MESSAGE_EXPIRY_INTERVAL, seconds)
+            })?;
+        }
+        Ok(properties)
+    }
+}
+
+// A property list under construction. It is freed when dropped, unless handed over to the broker.
+pub(crate) struct PropertyList {
+    list: *mut mosquitto_property,
+}
+
+impl PropertyList {
+    pub(crate) fn new() -> PropertyList {
+        PropertyList { list: null_mut() }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        add: impl FnOnce(*mut *mut mosquitto_property) -> c_int,
+    ) -> Result<Success, Error> {
+        match add(&mut self.list) {
+            0 => Ok(Success),
+            error => Err(Error::from(error)),
+        }
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut mosquitto_property {
+        self.list
+    }
+
+    // Called once the broker took ownership of the list
+    pub(crate) fn forget(mut self) {
+        self.list = null_mut();
+    }
+}
+
+impl Drop for PropertyList {
+    fn drop(&mut self) {
+        if !self.list.is_null() {
+            unsafe { mosquitto_property_free_all(&mut self.list) };
+        }
+    }
+}
+
+// Converts a string copied out of a property list into an owned String, freeing the copy
+fn take_string(value: *mut c_char) -> String {
+    if value.is_null() {
+        return String::new();
+    }
+    let string = unsafe { CStr::from_ptr(value) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { mosquitto_free(value as _) };
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A list like a client sends it, the user properties split by other properties
+    fn received() -> PropertyList {
+        let mut list = PropertyList::new();
+        let add_pair = |list: &mut PropertyList, name: &[u8], value: &[u8]| {
+            list.add(|list| unsafe {
+                mosquitto_property_add_string_pair(
+                    list,
+                    MQTT_PROP_USER_PROPERTY,
+                    name.as_ptr() as *const c_char,
+                    value.as_ptr() as *const c_char,
+                )
+            })
+            .unwrap();
+        };
+        add_pair(&mut list, b"tenant\0", b"a\0");
+        list.add(|list| unsafe {
+            mosquitto_property_add_byte(list, MQTT_PROP_PAYLOAD_FORMAT_INDICATOR, 1)
+        })
+        .unwrap();
+        add_pair(&mut list, b"trace\0", b"1\0");
+        list.add(|list| unsafe {
+            mosquitto_property_add_string(
+                list,
+                MQTT_PROP_CONTENT_TYPE,
+                b"text/plain\0".as_ptr() as *const c_char,
+            )
+        })
+        .unwrap();
+        add_pair(&mut list, b"tenant\0", b"b\0");
+        list.add(|list| unsafe {
+            mosquitto_property_add_string(
+                list,
+                MQTT_PROP_RESPONSE_TOPIC,
+                b"reply\0".as_ptr() as *const c_char,
+            )
+        })
+        .unwrap();
+        list.add(|list| unsafe {
+            mosquitto_property_add_binary(
+                list,
+                MQTT_PROP_CORRELATION_DATA,
+                b"\x00\x2a".as_ptr() as *const c_void,
+                2,
+            )
+        })
+        .unwrap();
+        list.add(|list| unsafe {
+            mosquitto_property_add_int32(list, MQTT_PROP_MESSAGE_EXPIRY_INTERVAL, 30)
+        })
+        .unwrap();
+        list
+    }
+
+    #[test]
+    fn reads_a_received_list() {
+        let mut list = received();
+        let properties = unsafe { Properties::from_ptr(list.as_mut_ptr()) };
+
+        assert!(!properties.is_empty());
+        assert_eq!(properties.content_type().as_deref(), Some("text/plain"));
+        assert_eq!(properties.response_topic().as_deref(), Some("reply"));
+        assert_eq!(properties.correlation_data(), Some(vec![0x00, 0x2a]));
+        assert_eq!(properties.message_expiry_interval(), Some(30));
+        assert_eq!(
+            properties.user_properties(),
+            vec![
+                ("tenant".to_string(), "a".to_string()),
+                ("trace".to_string(), "1".to_string()),
+                ("tenant".to_string(), "b".to_string()),
+            ]
+        );
+        assert_eq!(properties.user_property("tenant").as_deref(), Some("a"));
+        assert_eq!(properties.user_property("trace").as_deref(), Some("1"));
+        assert_eq!(properties.user_property("missing"), None);
+    }
+
+    #[test]
+    fn reads_nothing_from_an_empty_list() {
+        let properties = Properties::empty();
+
+        assert!(properties.is_empty());
+        assert_eq!(properties.content_type(), None);
+        assert_eq!(properties.correlation_data(), None);
+        assert_eq!(properties.message_expiry_interval(), None);
+        assert!(properties.user_properties().is_empty());
+        assert_eq!(properties.into_owned(), OwnedProperties::default());
+        assert_eq!(format!("{:?}", properties), "Properties {}");
+    }
+
+    #[test]
+    fn builds_the_list_it_reads() {
+        let mut received = received();
+        let owned = unsafe { Properties::from_ptr(received.as_mut_ptr()) }.into_owned();
+
+        let mut list = owned.to_list().unwrap();
+        let properties = unsafe { Properties::from_ptr(list.as_mut_ptr()) };
+        assert_eq!(properties.into_owned(), owned);
+
+        let nul = OwnedProperties {
+            content_type: Some("text\0plain".to_string()),
+            ..OwnedProperties::default()
+        };
+        assert!(matches!(nul.to_list(), Err(Error::Inval)));
+        let too_long = OwnedProperties {
+            correlation_data: Some(vec![0; 65536]),
+            ..OwnedProperties::default()
+        };
+        assert!(matches!(too_long.to_list(), Err(Error::Inval)));
+    }
+}
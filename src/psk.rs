@@ -0,0 +1,98 @@
+// Pre-shared keys for TLS-PSK listeners.
+//
+// Mosquitto asks the plugin for the key of an identity with the PSK key event and expects it
+// written hex encoded into a buffer of the event, nul terminated and at most max_key_len
+// characters long. The buffer is allocated one byte larger than that for the terminator.
+
+use crate::Error;
+use std::fmt;
+use std::os::raw::{c_char, c_int};
+
+/// A TLS pre-shared key
+#[derive(Clone, PartialEq, Eq)]
+pub struct PskKey {
+    key: Vec<u8>,
+}
+
+impl PskKey {
+    pub fn from_bytes(key: impl Into<Vec<u8>>) -> PskKey {
+        PskKey { key: key.into() }
+    }
+
+    /// Decodes a hex encoded key like those in a mosquitto psk_file, None if it isn't hex
+    pub fn from_hex(hex: &str) -> Option<PskKey> {
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let key = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| match pair {
+                [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<u8>>>()?;
+        Some(PskKey { key })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The key hex encoded, as mosquitto takes it
+    pub fn to_hex(&self) -> String {
+        self.key.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // Writes the key into the buffer of a PSK key event. `buffer` has to be null or hold
+    // `max_key_len` + 1 bytes.
+    pub(crate) fn write_to(&self, buffer: *mut c_char, max_key_len: c_int) -> Result<(), Error> {
+        let hex = self.to_hex();
+        if buffer.is_null() || max_key_len < 0 || hex.len() > max_key_len as usize {
+            return Err(Error::Inval);
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(hex.as_ptr(), buffer as *mut u8, hex.len());
+            *buffer.add(hex.len()) = 0;
+        }
+        Ok(())
+    }
+}
+
+// The key stays out of logs
+impl fmt::Debug for PskKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PskKey({} bytes)", self.key.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_from_and_to_hex() {
+        let key = PskKey::from_hex("DEADbeef00").unwrap();
+        assert_eq!(key.as_bytes(), [0xde, 0xad, 0xbe, 0xef, 0x00]);
+        assert_eq!(key.to_hex(), "deadbeef00");
+        assert_eq!(format!("{:?}", key), "PskKey(5 bytes)");
+
+        assert_eq!(PskKey::from_hex("abc"), None);
+        assert_eq!(PskKey::from_hex("zz"), None);
+        assert_eq!(PskKey::from_hex("+f"), None);
+        assert_eq!(PskKey::from_hex("éé"), None);
+    }
+
+    #[test]
+    fn writes_only_keys_that_fit() {
+        let key = PskKey::from_bytes(vec![0xab, 0xcd]);
+        let mut buffer = [0x7fu8; 6];
+        let ptr = buffer.as_mut_ptr() as *mut c_char;
+
+        assert_eq!(key.write_to(ptr, 3), Err(Error::Inval));
+        assert_eq!(buffer, [0x7f; 6]);
+        assert_eq!(key.write_to(ptr, 4), Ok(()));
+        assert_eq!(&buffer, b"abcd\0\x7f");
+        assert_eq!(key.write_to(std::ptr::null_mut(), 4), Err(Error::Inval));
+    }
+}
@@ -0,0 +1,374 @@
+// Timers on top of the tick event.
+//
+// Mosquitto calls the tick callbacks from its main loop, so tasks run on the broker thread with
+// mutable access to the plugin and nothing has to be synchronized. A task runs on the first tick
+// at or after its deadline, the timing is only as fine as the ticks, about every 100 ms.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::time::Duration;
+
+/// The time of the monotonic clock mosquitto keeps its own time with, CLOCK_MONOTONIC
+#[cfg(unix)]
+pub fn monotonic_now() -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+/// The time since the plugin first asked for it. Monotonic as well, but not the clock of the
+/// broker, so it only steps the tasks of the plugin.
+#[cfg(not(unix))]
+pub fn monotonic_now() -> Duration {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed()
+}
+
+/// Identifies a scheduled task, to cancel it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+enum Run<T> {
+    Once(Box<dyn FnOnce(&mut T)>),
+    Every(Duration, Box<dyn FnMut(&mut T)>),
+}
+
+struct Task<T> {
+    id: TaskId,
+    due: Duration,
+    run: Run<T>,
+}
+
+/// Tasks that run on the tick event with `&mut` access to the plugin.
+///
+/// The plugin keeps the scheduler in a field and hands it out from
+/// `MosquittoPlugin::scheduler`, and has to register for `EventSet::TICK`. A task that panics
+/// doesn't keep the other tasks due on the same tick from running, and a periodic one stays
+/// scheduled. The panic is passed on after them, to the panic policy of the plugin.
+///
+/// ```
+/// use mosquitto_plugin::scheduler::Scheduler;
+/// use mosquitto_plugin::*;
+/// use std::time::Duration;
+///
+/// #[derive(Debug)]
+/// struct Plugin {
+///     scheduler: Scheduler<Plugin>,
+///     cache: Vec<String>,
+/// }
+///
+/// impl MosquittoPlugin for Plugin {
+///     const EVENTS: EventSet = EventSet::TICK.union(EventSet::ACL_CHECK);
+///
+///     fn init(_opts: MosquittoOpt) -> Result<Self, InitError> {
+///         let mut scheduler = Scheduler::new();
+///         scheduler.schedule_every(Duration::from_secs(60), |plugin: &mut Plugin| {
+///             plugin.cache.clear()
+///         });
+///         Ok(Plugin {
+///             scheduler,
+///             cache: Vec::new(),
+///         })
+///     }
+///
+///     fn scheduler(&mut self) -> Option<&mut Scheduler<Self>> {
+///         Some(&mut self.scheduler)
+///     }
+/// }
+/// ```
+pub struct Scheduler<T> {
+    tasks: Vec<Task<T>>,
+    next_id: u64,
+    // The time of the last tick, deadlines of tasks scheduled before the first tick are relative
+    now: Option<Duration>,
+    // The tasks taken out to run, and those of them cancelled while running
+    running: HashSet<TaskId>,
+    cancelled: HashSet<TaskId>,
+}
+
+impl<T> Scheduler<T> {
+    pub fn new() -> Scheduler<T> {
+        Scheduler {
+            tasks: Vec::new(),
+            next_id: 0,
+            now: None,
+            running: HashSet::new(),
+            cancelled: HashSet::new(),
+        }
+    }
+
+    /// Runs `task` once, on the first tick `delay` after the last one
+    pub fn schedule_once<F>(&mut self, delay: Duration, task: F) -> TaskId
+    where
+        F: FnOnce(&mut T) + 'static,
+    {
+        self.add(delay, Run::Once(Box::new(task)))
+    }
+
+    /// Runs `task` every `interval`, starting `interval` after the last tick. Runs that were
+    /// missed because ticks came late are skipped, a zero interval runs on every tick.
+    pub fn schedule_every<F>(&mut self, interval: Duration, task: F) -> TaskId
+    where
+        F: FnMut(&mut T) + 'static,
+    {
+        self.add(interval, Run::Every(interval, Box::new(task)))
+    }
+
+    /// Cancels the task, false if it already ran or was cancelled. A task can cancel itself.
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        if let Some(position) = self.tasks.iter().position(|task| task.id == id) {
+            self.tasks.remove(position);
+            true
+        } else if self.running.remove(&id) {
+            self.cancelled.insert(id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The number of scheduled tasks
+    pub fn len(&self) -> usize {
+        self.tasks.len() + self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn add(&mut self, delay: Duration, run: Run<T>) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push(Task {
+            id,
+            due: self.now.unwrap_or_default() + delay,
+            run,
+        });
+        id
+    }
+
+    // Takes out the tasks due at `now`, in the order of their deadlines
+    fn take_due(&mut self, now: Duration) -> Vec<Task<T>> {
+        if self.now.is_none() {
+            for task in &mut self.tasks {
+                task.due += now;
+            }
+        }
+        self.now = Some(now);
+
+        let (mut due, pending): (Vec<_>, Vec<_>) =
+            self.tasks.drain(..).partition(|task| task.due <= now);
+        self.tasks = pending;
+        due.sort_by_key(|task| (task.due, task.id.0));
+        self.running = due.iter().map(|task| task.id).collect();
+        due
+    }
+
+    // Puts a periodic task back after it ran, unless it was cancelled meanwhile
+    fn reschedule(&mut self, mut task: Task<T>, interval: Duration, now: Duration) {
+        if self.cancelled.remove(&task.id) {
+            return;
+        }
+        self.running.remove(&task.id);
+        task.due = if interval.as_nanos() == 0 {
+            now
+        } else {
+            let behind = (now - task.due).as_nanos() / interval.as_nanos();
+            task.due + Duration::from_nanos((interval.as_nanos() * (behind + 1)) as u64)
+        };
+        self.tasks.push(task);
+    }
+}
+
+impl<T> Default for Scheduler<T> {
+    fn default() -> Scheduler<T> {
+        Scheduler::new()
+    }
+}
+
+impl<T> fmt::Debug for Scheduler<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("tasks", &self.len())
+            .field("now", &self.now)
+            .finish()
+    }
+}
+
+/// Runs the tasks of the plugin due at `now`. `scheduler` returns the scheduler of the plugin,
+/// it is looked up again after each task, so tasks can schedule and cancel others. If tasks
+/// panic, the first panic is resumed once all of them ran.
+pub fn run_due<T, F>(plugin: &mut T, now: Duration, scheduler: F)
+where
+    F: Fn(&mut T) -> Option<&mut Scheduler<T>>,
+{
+    let due = match scheduler(plugin) {
+        Some(scheduler) => scheduler.take_due(now),
+        None => return,
+    };
+    let mut panic = None;
+    for task in due {
+        let Task { id, due, run } = task;
+        match run {
+            Run::Once(run) => {
+                if let Some(scheduler) = scheduler(plugin) {
+                    if scheduler.cancelled.remove(&id) || !scheduler.running.remove(&id) {
+                        continue;
+                    }
+                }
+                if let Err(payload) = catch_unwind(AssertUnwindSafe(|| run(plugin))) {
+                    panic.get_or_insert(payload);
+                }
+            }
+            Run::Every(interval, mut run) => {
+                let cancelled = scheduler(plugin).is_some_and(|s| s.cancelled.contains(&id));
+                if !cancelled {
+                    if let Err(payload) = catch_unwind(AssertUnwindSafe(|| run(plugin))) {
+                        panic.get_or_insert(payload);
+                    }
+                }
+                if let Some(scheduler) = scheduler(plugin) {
+                    let task = Task {
+                        id,
+                        due,
+                        run: Run::Every(interval, run),
+                    };
+                    scheduler.reschedule(task, interval, now);
+                }
+            }
+        }
+    }
+    if let Some(payload) = panic {
+        resume_unwind(payload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Plugin {
+        scheduler: Scheduler<Plugin>,
+        runs: Vec<&'static str>,
+        task: Option<TaskId>,
+    }
+
+    fn scheduler(plugin: &mut Plugin) -> Option<&mut Scheduler<Plugin>> {
+        Some(&mut plugin.scheduler)
+    }
+
+    fn tick(plugin: &mut Plugin, ms: u64) -> Vec<&'static str> {
+        run_due(plugin, Duration::from_millis(ms), scheduler);
+        plugin.runs.drain(..).collect()
+    }
+
+    #[test]
+    fn runs_tasks_on_the_first_tick_after_their_deadline() {
+        let mut plugin = Plugin::default();
+        plugin
+            .scheduler
+            .schedule_once(Duration::from_millis(250), |p: &mut Plugin| {
+                p.runs.push("once")
+            });
+        plugin
+            .scheduler
+            .schedule_every(Duration::from_millis(100), |p: &mut Plugin| {
+                p.runs.push("every")
+            });
+
+        // Deadlines count from the first tick
+        assert!(tick(&mut plugin, 10_000).is_empty());
+        assert_eq!(tick(&mut plugin, 10_100), ["every"]);
+        assert_eq!(tick(&mut plugin, 10_150), [] as [&str; 0]);
+        assert_eq!(tick(&mut plugin, 10_260), ["every", "once"]);
+        assert_eq!(plugin.scheduler.len(), 1);
+
+        // Missed runs are skipped, the period stays aligned
+        assert_eq!(tick(&mut plugin, 10_750), ["every"]);
+        assert_eq!(tick(&mut plugin, 10_790), [] as [&str; 0]);
+        assert_eq!(tick(&mut plugin, 10_800), ["every"]);
+    }
+
+    #[test]
+    fn cancels_tasks_including_from_within_themselves() {
+        let mut plugin = Plugin::default();
+        let once = plugin
+            .scheduler
+            .schedule_once(Duration::from_millis(100), |p: &mut Plugin| {
+                p.runs.push("once")
+            });
+        let every = plugin
+            .scheduler
+            .schedule_every(Duration::ZERO, |p: &mut Plugin| {
+                p.runs.push("every");
+                if let Some(id) = p.task.take() {
+                    assert!(p.scheduler.cancel(id));
+                }
+            });
+
+        assert!(plugin.scheduler.cancel(once));
+        assert!(!plugin.scheduler.cancel(once));
+
+        // Runs on every tick until it cancels itself
+        assert_eq!(tick(&mut plugin, 0), ["every"]);
+        assert_eq!(tick(&mut plugin, 1), ["every"]);
+        plugin.task = Some(every);
+        assert_eq!(tick(&mut plugin, 2), ["every"]);
+        assert!(plugin.scheduler.is_empty());
+        assert!(tick(&mut plugin, 3).is_empty());
+    }
+
+    #[test]
+    fn keeps_running_tasks_when_one_panics() {
+        let mut plugin = Plugin::default();
+        let panicking = plugin
+            .scheduler
+            .schedule_once(Duration::from_millis(100), |_: &mut Plugin| {
+                panic!("task failed")
+            });
+        let every = plugin
+            .scheduler
+            .schedule_every(Duration::from_millis(100), |p: &mut Plugin| {
+                p.runs.push("every")
+            });
+
+        assert!(tick(&mut plugin, 0).is_empty());
+        let panic = catch_unwind(AssertUnwindSafe(|| {
+            run_due(&mut plugin, Duration::from_millis(100), scheduler)
+        }))
+        .unwrap_err();
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"task failed"));
+
+        // The periodic task ran and stays scheduled, the panicking one is gone
+        assert_eq!(plugin.runs.drain(..).collect::<Vec<_>>(), ["every"]);
+        assert_eq!(plugin.scheduler.len(), 1);
+        assert!(!plugin.scheduler.cancel(panicking));
+        assert_eq!(tick(&mut plugin, 200), ["every"]);
+        assert!(plugin.scheduler.cancel(every));
+        assert!(plugin.scheduler.is_empty());
+    }
+
+    #[test]
+    fn tasks_schedule_further_tasks() {
+        let mut plugin = Plugin::default();
+        plugin
+            .scheduler
+            .schedule_once(Duration::from_secs(1), |p: &mut Plugin| {
+                p.runs.push("first");
+                p.scheduler
+                    .schedule_once(Duration::from_secs(1), |p: &mut Plugin| {
+                        p.runs.push("second")
+                    });
+            });
+
+        assert!(tick(&mut plugin, 0).is_empty());
+        assert_eq!(tick(&mut plugin, 1_000), ["first"]);
+        assert!(tick(&mut plugin, 1_500).is_empty());
+        assert_eq!(tick(&mut plugin, 2_000), ["second"]);
+    }
+}
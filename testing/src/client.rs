@@ -0,0 +1,250 @@
+use mosquitto_plugin::mosquitto_dev::{mosquitto, mosquitto_protocol_mp_mqtt};
+use mosquitto_plugin::{
+    Certificate, Error, Listener, MosquittoClientContext, MosquittoClientProtocol,
+    MosquittoClientProtocolVersion, Success,
+};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::net::IpAddr;
+use std::os::raw::c_int;
+
+/// A client for tests, built up with the setters below.
+///
+/// It implements [`MosquittoClientContext`], so it can be handed to the plugin's callbacks
+/// directly. It also stands in for the broker's client struct when passed to a
+/// [`MockBroker`](super::MockBroker), where the mock `mosquitto_client_*` functions read it through
+/// the `*mut mosquitto` given to the plugin. Either way `set_username` calls are recorded.
+///
+/// ```no_run
+/// use mosquitto_plugin_testing::FakeClient;
+/// use mosquitto_plugin::*;
+///
+/// let client = FakeClient::new("sensor-1")
+///     .username("sensor")
+///     .address("10.0.0.7".parse().unwrap())
+///     .protocol_version(MosquittoClientProtocolVersion::V4);
+///
+/// client.set_username("tenant/sensor".to_string()).unwrap();
+/// assert_eq!(client.get_username().as_deref(), Some("tenant/sensor"));
+/// assert_eq!(client.set_username_calls(), vec![Some("tenant/sensor".to_string())]);
+/// ```
+#[derive(Debug)]
+pub struct FakeClient {
+    pub(crate) id: Option<CString>,
+    pub(crate) username: RefCell<Option<CString>>,
+    pub(crate) address: Option<CString>,
+    pub(crate) protocol: c_int,
+    // The listener port, 0 for a client without listener
+    pub(crate) port: c_int,
+    pub(crate) protocol_version: c_int,
+    pub(crate) keepalive: c_int,
+    pub(crate) clean_session: bool,
+    pub(crate) sub_count: c_int,
+    pub(crate) certificate: Option<Vec<u8>>,
+    set_username_calls: RefCell<Vec<Option<String>>>,
+}
+
+impl FakeClient {
+    /// A MQTT v5 client with a clean session, connected from localhost to port 1883 without
+    /// username
+    pub fn new(id: &str) -> FakeClient {
+        FakeClient {
+            id: Some(c_string(id.as_bytes(), "client id")),
+            username: RefCell::new(None),
+            address: Some(CString::new("127.0.0.1").unwrap()),
+            protocol: mosquitto_protocol_mp_mqtt as c_int,
+            port: 1883,
+            protocol_version: 5,
+            keepalive: 60,
+            clean_session: true,
+            sub_count: 0,
+            certificate: None,
+            set_username_calls: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// A client without client id, as seen before the broker assigned one
+    pub fn without_id(mut self) -> FakeClient {
+        self.id = None;
+        self
+    }
+
+    /// A client id that isn't valid UTF-8, or any other bytes without nul
+    pub fn id_bytes(mut self, id: &[u8]) -> FakeClient {
+        self.id = Some(c_string(id, "client id"));
+        self
+    }
+
+    pub fn username(self, username: &str) -> FakeClient {
+        self.username_bytes(username.as_bytes())
+    }
+
+    /// A username that isn't valid UTF-8, or any other bytes without nul
+    pub fn username_bytes(self, username: &[u8]) -> FakeClient {
+        *self.username.borrow_mut() = Some(c_string(username, "username"));
+        self
+    }
+
+    pub fn address(mut self, address: IpAddr) -> FakeClient {
+        self.address = Some(CString::new(address.to_string()).unwrap());
+        self
+    }
+
+    /// An address as the broker reports it, which needn't be an IP address, like for clients on
+    /// unix sockets
+    pub fn address_str(mut self, address: &str) -> FakeClient {
+        self.address = Some(c_string(address.as_bytes(), "address"));
+        self
+    }
+
+    /// A client without address, like one of a stored session after a broker restart
+    pub fn without_address(mut self) -> FakeClient {
+        self.address = None;
+        self
+    }
+
+    pub fn protocol(mut self, protocol: MosquittoClientProtocol) -> FakeClient {
+        self.protocol = protocol.into();
+        self
+    }
+
+    /// The port of the listener the client connected to. Only visible to the plugin when
+    /// building for mosquitto 2.1, like with the broker, on 2.0 the listener has no port.
+    pub fn port(mut self, port: u16) -> FakeClient {
+        self.port = port as c_int;
+        self
+    }
+
+    /// A client without listener, like one of a stored session after a broker restart
+    pub fn without_listener(mut self) -> FakeClient {
+        self.port = 0;
+        self
+    }
+
+    pub fn protocol_version(mut self, version: MosquittoClientProtocolVersion) -> FakeClient {
+        self.protocol_version = version.into();
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: i32) -> FakeClient {
+        self.keepalive = keepalive;
+        self
+    }
+
+    pub fn clean_session(mut self, clean_session: bool) -> FakeClient {
+        self.clean_session = clean_session;
+        self
+    }
+
+    pub fn sub_count(mut self, sub_count: i32) -> FakeClient {
+        self.sub_count = sub_count;
+        self
+    }
+
+    /// The DER encoded certificate the client presented on a TLS listener
+    pub fn certificate(mut self, der: impl Into<Vec<u8>>) -> FakeClient {
+        self.certificate = Some(der.into());
+        self
+    }
+
+    /// The usernames set by the plugin so far, `None` where it was cleared
+    pub fn set_username_calls(&self) -> Vec<Option<String>> {
+        self.set_username_calls.borrow().clone()
+    }
+
+    /// The handle passed to the plugin in place of the broker's client struct
+    pub fn as_ptr(&self) -> *mut mosquitto {
+        self as *const FakeClient as *mut mosquitto
+    }
+
+    pub(crate) fn replace_username(&self, username: Option<CString>) {
+        self.set_username_calls.borrow_mut().push(
+            username
+                .as_ref()
+                .map(|username| username.to_string_lossy().into_owned()),
+        );
+        *self.username.borrow_mut() = username;
+    }
+}
+
+fn c_string(s: &[u8], what: &str) -> CString {
+    CString::new(s).unwrap_or_else(|_| panic!("{} contains a nul byte", what))
+}
+
+// Like the broker functions, strings that aren't valid UTF-8 read as None
+fn to_string(s: &CStr) -> Option<String> {
+    s.to_str().ok().map(String::from)
+}
+
+impl MosquittoClientContext for FakeClient {
+    fn get_address(&self) -> Option<IpAddr> {
+        self.address
+            .as_ref()
+            .and_then(|address| address.to_str().ok()?.parse().ok())
+    }
+
+    fn is_clean_session(&self) -> bool {
+        self.clean_session
+    }
+
+    fn get_id(&self) -> Option<String> {
+        self.id.as_deref().and_then(to_string)
+    }
+
+    fn get_id_bytes(&self) -> Option<Vec<u8>> {
+        self.id.as_ref().map(|id| id.as_bytes().to_vec())
+    }
+
+    fn get_keepalive(&self) -> i32 {
+        self.keepalive
+    }
+
+    fn get_certificate(&self) -> Option<Certificate> {
+        self.certificate.clone().map(Certificate::from_der)
+    }
+
+    fn get_protocol(&self) -> MosquittoClientProtocol {
+        self.protocol.into()
+    }
+
+    fn get_protocol_version(&self) -> MosquittoClientProtocolVersion {
+        self.protocol_version.into()
+    }
+
+    fn get_sub_count(&self) -> i32 {
+        self.sub_count
+    }
+
+    fn get_username(&self) -> Option<String> {
+        self.username.borrow().as_deref().and_then(to_string)
+    }
+
+    fn get_username_bytes(&self) -> Option<Vec<u8>> {
+        self.username
+            .borrow()
+            .as_ref()
+            .map(|username| username.as_bytes().to_vec())
+    }
+
+    fn set_username(&self, username: String) -> Result<Success, Error> {
+        let username = CString::new(username).map_err(|_| Error::Inval)?;
+        self.replace_username(Some(username));
+        Ok(Success)
+    }
+
+    #[cfg(feature = "mosq-2-0")]
+    fn get_listener(&self) -> Option<Listener> {
+        Some(Listener {
+            port: None,
+            protocol: self.get_protocol(),
+        })
+    }
+
+    #[cfg(feature = "mosq-2-1")]
+    fn get_listener(&self) -> Option<Listener> {
+        (self.port > 0).then(|| Listener {
+            port: Some(self.port as u16),
+            protocol: self.get_protocol(),
+        })
+    }
+}
@@ -0,0 +1,847 @@
+//! An in-process stand-in for mosquitto, to unit test plugins with plain `cargo test`.
+//!
+//! The crate provides its own implementations of the `mosquitto_*` functions mosquitto-plugin
+//! calls, so a test binary links without the broker. [`MockBroker`] loads the plugin created by
+//! `create_dynamic_library!` through `mosquitto_plugin_init`, fires events at the registered
+//! callbacks and records everything the plugin publishes, kicks and logs.
+//!
+//! Only take it as a dev-dependency. The mocks are exported under the names of the broker
+//! functions, in a plugin they would shadow those of mosquitto. It has the same `mosq-2-0` and
+//! `mosq-2-1` features as mosquitto-plugin, the one enabled has to be the same for both.
+//!
+//! ```no_run
+//! use mosquitto_plugin_testing::{FakeClient, MockBroker};
+//! use mosquitto_plugin::*;
+//!
+//! # #[derive(Debug)]
+//! # struct Plugin;
+//! # impl MosquittoPlugin for Plugin {
+//! #     fn init(_opts: MosquittoOpt) -> Result<Self, InitError> { Ok(Plugin) }
+//! # }
+//! create_dynamic_library!(Plugin);
+//!
+//! # fn main() {
+//! let mut broker = MockBroker::new();
+//! let plugin = broker.load_plugin(&[("topic", "allowed")]).unwrap();
+//!
+//! let client = FakeClient::new("client");
+//! let message = OwnedMessage {
+//!     topic: "allowed".to_string(),
+//!     payload: b"hello".to_vec(),
+//!     qos: 0,
+//!     retain: false,
+//!     properties: None,
+//! };
+//! assert_eq!(
+//!     broker.acl_check(&client, AclCheckAccessLevel::Write, &message),
+//!     Ok(Success)
+//! );
+//! broker.unload_plugin(plugin).unwrap();
+//! # }
+//! ```
+//!
+//! The plugin can be loaded several times, like under several `plugin` lines of a configuration.
+//! Each instance has its own set of callbacks, which corresponds to mosquitto with
+//! `per_listener_settings true`. Events are fired at the callbacks of all loaded instances.
+//!
+//! The mocks keep their state per thread, and a `MockBroker` has to stay on the thread that
+//! created it. Since `create_dynamic_library!` exports fixed symbol names, a test binary can hold
+//! only one plugin type.
+
+mod client;
+mod symbols;
+
+pub use client::FakeClient;
+
+use mosquitto_plugin::mosquitto_dev::*;
+use mosquitto_plugin::{
+    AclCheckAccessLevel, AuthOutcome, DisconnectReason, Error, MosquittoPluginEvent, OwnedMessage,
+    Properties, Success,
+};
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr::{null, null_mut};
+use std::time::Duration;
+use symbols::{BrokerState, Callback, BROKER};
+
+const MOSQ_ERR_AUTH_CONTINUE: c_int = -4;
+const MOSQ_ERR_PLUGIN_DEFER: c_int = 17;
+
+/// A message the plugin published through the broker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedMessage {
+    /// The receiving client, for messages sent to a single client
+    pub client_id: Option<String>,
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: i32,
+    pub retain: bool,
+    pub user_properties: Vec<(String, String)>,
+    pub content_type: Option<String>,
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+    pub message_expiry_interval: Option<u32>,
+}
+
+/// A client disconnected by the plugin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kick {
+    All { with_will: bool },
+    ClientId { client_id: String, with_will: bool },
+    Username { username: String, with_will: bool },
+}
+
+/// A message the plugin logged, `level` is one of the MOSQ_LOG_* values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub level: i32,
+    pub message: String,
+}
+
+/// A plugin instance loaded by [`MockBroker::load_plugin`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PluginId(usize);
+
+/// Drives the plugin like mosquitto would, see the [module documentation](self).
+pub struct MockBroker {
+    plugins: Vec<LoadedPlugin>,
+    // Identifiers of plugins that were unloaded, kept so they stay unique
+    unloaded: Vec<Box<[u8]>>,
+    next_id: usize,
+    _thread_bound: PhantomData<*mut ()>,
+}
+
+struct LoadedPlugin {
+    id: PluginId,
+    // Stands in for the broker's opaque mosquitto_plugin_id_t, only its address matters
+    identifier: Box<[u8]>,
+    user_data: *mut c_void,
+    options: Options,
+}
+
+impl LoadedPlugin {
+    fn identifier(&self) -> *mut mosquitto_plugin_id_t {
+        self.identifier.as_ptr() as *mut mosquitto_plugin_id_t
+    }
+}
+
+// plugin_opt_* options as the broker passes them, the strings live as long as the array
+struct Options {
+    _strings: Vec<CString>,
+    opts: Vec<mosquitto_opt>,
+}
+
+impl Options {
+    fn new(options: &[(&str, &str)]) -> Options {
+        let mut strings = Vec::new();
+        let mut opts = Vec::new();
+        for (key, value) in options {
+            let key = CString::new(*key).expect("option key contains a nul byte");
+            let value = CString::new(*value).expect("option value contains a nul byte");
+            opts.push(mosquitto_opt {
+                key: key.as_ptr() as *mut c_char,
+                value: value.as_ptr() as *mut c_char,
+            });
+            strings.push(key);
+            strings.push(value);
+        }
+        Options {
+            _strings: strings,
+            opts,
+        }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut mosquitto_opt {
+        self.opts.as_mut_ptr()
+    }
+
+    fn count(&self) -> c_int {
+        self.opts.len() as c_int
+    }
+}
+
+fn result(rc: c_int) -> Result<Success, Error> {
+    match rc {
+        0 => Ok(Success),
+        rc => Err(Error::from(rc)),
+    }
+}
+
+fn c_string(s: &str, what: &str) -> CString {
+    CString::new(s).unwrap_or_else(|_| panic!("{} contains a nul byte", what))
+}
+
+impl MockBroker {
+    /// Starts a broker without plugins, clearing anything recorded on this thread before
+    pub fn new() -> MockBroker {
+        BROKER.with(|broker| *broker.borrow_mut() = BrokerState::default());
+        mosquitto_plugin::certificate::__use_libcrypto(symbols::i2d_x509, symbols::x509_free);
+        MockBroker {
+            plugins: Vec::new(),
+            unloaded: Vec::new(),
+            next_id: 0,
+            _thread_bound: PhantomData,
+        }
+    }
+
+    /// Calls `mosquitto_plugin_init` with the options given as `plugin_opt_<key> <value>` in the
+    /// configuration. Every call loads another instance of the plugin. When init fails,
+    /// `mosquitto_plugin_cleanup` is called right away, as mosquitto does when it shuts down.
+    pub fn load_plugin(&mut self, options: &[(&str, &str)]) -> Result<PluginId, Error> {
+        // Offered in the order of preference of the broker
+        let versions = [5, 4, 3, 2];
+        let version =
+            unsafe { mosquitto_plugin_version(versions.len() as c_int, versions.as_ptr()) };
+        if version != MOSQ_PLUGIN_VERSION as c_int {
+            return Err(Error::NotSupported);
+        }
+
+        let mut plugin = LoadedPlugin {
+            id: PluginId(self.next_id),
+            identifier: Box::new([0]),
+            user_data: null_mut(),
+            options: Options::new(options),
+        };
+        self.next_id += 1;
+        let rc = unsafe {
+            mosquitto_plugin_init(
+                plugin.identifier(),
+                &mut plugin.user_data,
+                plugin.options.as_mut_ptr(),
+                plugin.options.count(),
+            )
+        };
+        if rc != 0 {
+            // mosquitto refuses to start, and calls cleanup for the plugin when shutting down
+            let cleanup_rc = unsafe {
+                mosquitto_plugin_cleanup(
+                    plugin.user_data,
+                    plugin.options.as_mut_ptr(),
+                    plugin.options.count(),
+                )
+            };
+            assert_eq!(
+                cleanup_rc, 0,
+                "cleanup failed for a plugin that failed to initialize"
+            );
+            self.unloaded.push(plugin.identifier);
+            return Err(Error::from(rc));
+        }
+        let id = plugin.id;
+        self.plugins.push(plugin);
+        Ok(id)
+    }
+
+    /// Calls `mosquitto_plugin_cleanup` for the instance. Callbacks it left registered stay in
+    /// the table, firing their event afterwards panics instead of passing on the freed user data.
+    pub fn unload_plugin(&mut self, id: PluginId) -> Result<Success, Error> {
+        let position = self
+            .plugins
+            .iter()
+            .position(|plugin| plugin.id == id)
+            .ok_or(Error::NotFound)?;
+        let mut plugin = self.plugins.remove(position);
+        let rc = unsafe {
+            mosquitto_plugin_cleanup(
+                plugin.user_data,
+                plugin.options.as_mut_ptr(),
+                plugin.options.count(),
+            )
+        };
+        self.unloaded.push(plugin.identifier);
+        result(rc)
+    }
+
+    /// Sends the reload event to the instance with a new set of options, as on SIGHUP.
+    /// Returns the error of the first reload callback that failed.
+    pub fn reload(&mut self, id: PluginId, options: &[(&str, &str)]) -> Result<Success, Error> {
+        let position = self
+            .plugins
+            .iter()
+            .position(|plugin| plugin.id == id)
+            .ok_or(Error::NotFound)?;
+        let identifier = self.plugins[position].identifier();
+        let mut options = Options::new(options);
+        let mut event_data = mosquitto_evt_reload {
+            future: null_mut(),
+            options: options.as_mut_ptr(),
+            option_count: options.count(),
+            future2: [null_mut(); 4],
+        };
+        let event = MosquittoPluginEvent::MosqEvtReload;
+        let mut rc = 0;
+        for (callback_identifier, callback, userdata) in self.callbacks(event) {
+            if callback_identifier == identifier {
+                let callback_rc = unsafe {
+                    callback(
+                        event as c_int,
+                        &mut event_data as *mut _ as *mut c_void,
+                        userdata,
+                    )
+                };
+                if rc == 0 {
+                    rc = callback_rc;
+                }
+            }
+        }
+        self.plugins[position].options = options;
+        result(rc)
+    }
+
+    /// Asks for `access` to the topic of `message`, which the broker does when a client
+    /// publishes, subscribes or is about to receive a message. The properties of `message` are
+    /// passed along.
+    /// Returns `Err(Error::PluginDefer)` if no callback decided.
+    pub fn acl_check(
+        &mut self,
+        client: &FakeClient,
+        access: AclCheckAccessLevel,
+        message: &OwnedMessage,
+    ) -> Result<Success, Error> {
+        let topic = c_string(&message.topic, "topic");
+        let mut properties = message
+            .properties
+            .as_ref()
+            .map_or(null_mut(), symbols::property_list);
+        let mut event_data = mosquitto_evt_acl_check {
+            future: null_mut(),
+            client: client.as_ptr(),
+            topic: topic.as_ptr(),
+            payload: message.payload.as_ptr() as *const c_void,
+            properties,
+            access: access as c_int,
+            payloadlen: message.payload.len() as u32,
+            qos: message.qos as u8,
+            retain: message.retain,
+            future2: [null_mut(); 4],
+        };
+        let rc = self.fire_until_decided(MosquittoPluginEvent::MosqEvtAclCheck, &mut event_data);
+        unsafe { mosquitto_property_free_all(&mut properties) };
+        result(rc)
+    }
+
+    /// Checks the username and password of a connecting client.
+    /// Returns `Err(Error::PluginDefer)` if no callback decided.
+    pub fn basic_auth(
+        &mut self,
+        client: &FakeClient,
+        username: Option<&[u8]>,
+        password: Option<&[u8]>,
+    ) -> Result<Success, Error> {
+        let username =
+            username.map(|username| CString::new(username).expect("username contains a nul byte"));
+        let password =
+            password.map(|password| CString::new(password).expect("password contains a nul byte"));
+        let mut event_data = mosquitto_evt_basic_auth {
+            future: null_mut(),
+            client: client.as_ptr(),
+            username: username
+                .as_ref()
+                .map_or(null_mut(), |u| u.as_ptr() as *mut c_char),
+            password: password
+                .as_ref()
+                .map_or(null_mut(), |p| p.as_ptr() as *mut c_char),
+            future2: [null_mut(); 4],
+        };
+        result(self.fire_until_decided(MosquittoPluginEvent::MosqEvtBasicAuth, &mut event_data))
+    }
+
+    /// Starts extended authentication with `method`. The outcome carries the data the broker
+    /// would send to the client, an accept without data as `Accept(None)`.
+    pub fn auth_start(
+        &mut self,
+        client: &FakeClient,
+        method: &str,
+        data: Option<&[u8]>,
+    ) -> AuthOutcome {
+        self.extended_auth(
+            MosquittoPluginEvent::MosqEvtExtAuthStart,
+            client,
+            method,
+            data,
+        )
+    }
+
+    /// Continues extended authentication with the next AUTH packet of the client
+    pub fn auth_continue(
+        &mut self,
+        client: &FakeClient,
+        method: &str,
+        data: Option<&[u8]>,
+    ) -> AuthOutcome {
+        self.extended_auth(
+            MosquittoPluginEvent::MosqEvtExtAuthContinue,
+            client,
+            method,
+            data,
+        )
+    }
+
+    fn extended_auth(
+        &mut self,
+        event: MosquittoPluginEvent,
+        client: &FakeClient,
+        method: &str,
+        data: Option<&[u8]>,
+    ) -> AuthOutcome {
+        let method = c_string(method, "auth method");
+        let data_in_len = data.map_or(0, |data| {
+            assert!(data.len() <= u16::MAX as usize, "auth data is too long");
+            data.len() as u16
+        });
+        let mut event_data = mosquitto_evt_extended_auth {
+            future: null_mut(),
+            client: client.as_ptr(),
+            data_in: data.map_or(null(), |data| data.as_ptr() as *const c_void),
+            data_out: null_mut(),
+            data_in_len,
+            data_out_len: 0,
+            auth_method: method.as_ptr(),
+            future2: [null_mut(); 3],
+        };
+        let rc = self.fire_until_decided(event, &mut event_data);
+
+        // The broker sends data_out to the client and frees it
+        let data_out = if event_data.data_out.is_null() || event_data.data_out_len == 0 {
+            Vec::new()
+        } else {
+            let data_out = unsafe {
+                std::slice::from_raw_parts(
+                    event_data.data_out as *const u8,
+                    event_data.data_out_len as usize,
+                )
+            }
+            .to_vec();
+            unsafe { mosquitto_free(event_data.data_out) };
+            data_out
+        };
+        match rc {
+            0 if data_out.is_empty() => AuthOutcome::Accept(None),
+            0 => AuthOutcome::Accept(Some(data_out)),
+            MOSQ_ERR_AUTH_CONTINUE => AuthOutcome::Continue(data_out),
+            MOSQ_ERR_PLUGIN_DEFER => AuthOutcome::Defer,
+            _ => AuthOutcome::Deny,
+        }
+    }
+
+    /// Sends a message to a `$CONTROL/...` topic. Only the callback registered for exactly that
+    /// topic is called, `Err(Error::NotFound)` is returned if there is none.
+    pub fn control(
+        &mut self,
+        client: &FakeClient,
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<Success, Error> {
+        let topic = c_string(topic, "topic");
+        let mut event_data = mosquitto_evt_control {
+            future: null_mut(),
+            client: client.as_ptr(),
+            topic: topic.as_ptr(),
+            payload: payload.as_ptr() as *const c_void,
+            properties: null(),
+            reason_string: null_mut(),
+            payloadlen: payload.len() as u32,
+            qos: 0,
+            reason_code: 0,
+            retain: false,
+            future2: [null_mut(); 4],
+        };
+        let event = MosquittoPluginEvent::MosqEvtControl as c_int;
+        let callback = BROKER.with(|broker| {
+            broker
+                .borrow()
+                .registrations
+                .iter()
+                .find(|r| r.event == event && r.topic.as_deref() == Some(topic.as_c_str()))
+                .map(|r| (r.identifier, r.callback, r.userdata))
+        });
+        let (identifier, callback, userdata) = callback.ok_or(Error::NotFound)?;
+        self.assert_loaded(identifier, event);
+        let rc = unsafe { callback(event, &mut event_data as *mut _ as *mut c_void, userdata) };
+        if !event_data.reason_string.is_null() {
+            unsafe { mosquitto_free(event_data.reason_string as *mut c_void) };
+        }
+        result(rc)
+    }
+
+    /// Passes a message published by `client` through the message callbacks, and returns it as
+    /// it would be delivered. A plugin dropping the message makes this return its error.
+    /// The properties of the message are sent along, the returned message has None for them
+    /// when it is delivered without any.
+    pub fn message(
+        &mut self,
+        client: &FakeClient,
+        message: &OwnedMessage,
+    ) -> Result<OwnedMessage, Error> {
+        // The topic and payload are owned by the broker and may be swapped by the plugin
+        let topic = unsafe { mosquitto_strdup(c_string(&message.topic, "topic").as_ptr()) };
+        let payload = if message.payload.is_empty() {
+            null_mut()
+        } else {
+            let payload = unsafe { mosquitto_malloc(message.payload.len()) };
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    message.payload.as_ptr(),
+                    payload as *mut u8,
+                    message.payload.len(),
+                )
+            };
+            payload
+        };
+        let mut event_data = mosquitto_evt_message {
+            future: null_mut(),
+            client: client.as_ptr(),
+            topic,
+            payload,
+            properties: message
+                .properties
+                .as_ref()
+                .map_or(null_mut(), symbols::property_list),
+            reason_string: null_mut(),
+            payloadlen: message.payload.len() as u32,
+            qos: message.qos as u8,
+            reason_code: 0,
+            retain: message.retain,
+            future2: [null_mut(); 4],
+        };
+
+        let mut rc = 0;
+        for (_, callback, userdata) in self.callbacks(MosquittoPluginEvent::MosqEvtMessage) {
+            rc = unsafe {
+                callback(
+                    MosquittoPluginEvent::MosqEvtMessage as c_int,
+                    &mut event_data as *mut _ as *mut c_void,
+                    userdata,
+                )
+            };
+            if rc != 0 {
+                break;
+            }
+        }
+
+        let delivered = OwnedMessage {
+            topic: unsafe { CStr::from_ptr(event_data.topic) }
+                .to_string_lossy()
+                .into_owned(),
+            payload: if event_data.payload.is_null() {
+                Vec::new()
+            } else {
+                unsafe {
+                    std::slice::from_raw_parts(
+                        event_data.payload as *const u8,
+                        event_data.payloadlen as usize,
+                    )
+                }
+                .to_vec()
+            },
+            qos: event_data.qos as i32,
+            retain: event_data.retain,
+            properties: (!event_data.properties.is_null())
+                .then(|| unsafe { Properties::from_ptr(event_data.properties) }.into_owned()),
+        };
+        unsafe {
+            if event_data.payload != payload {
+                mosquitto_free(payload);
+            }
+            mosquitto_free(event_data.payload);
+            mosquitto_free(event_data.topic as *mut c_void);
+            mosquitto_property_free_all(&mut event_data.properties);
+            mosquitto_free(event_data.reason_string as *mut c_void);
+        }
+        result(rc).map(|_| delivered)
+    }
+
+    /// Asks for the pre-shared key of `identity`, giving the plugin a buffer of `max_key_len`
+    /// bytes. Returns what the plugin wrote into the buffer.
+    pub fn psk_key(
+        &mut self,
+        client: &FakeClient,
+        hint: &str,
+        identity: &str,
+        max_key_len: usize,
+    ) -> Result<String, Error> {
+        let hint = c_string(hint, "hint");
+        let identity = c_string(identity, "identity");
+        let mut key = vec![0u8; max_key_len + 1];
+        let mut event_data = mosquitto_evt_psk_key {
+            future: null_mut(),
+            client: client.as_ptr(),
+            hint: hint.as_ptr(),
+            identity: identity.as_ptr(),
+            key: key.as_mut_ptr() as *mut c_char,
+            max_key_len: max_key_len as c_int,
+            future2: [null_mut(); 4],
+        };
+        result(self.fire_until_decided(MosquittoPluginEvent::MosqEvtPskKey, &mut event_data))?;
+        let len = key.iter().position(|b| *b == 0).unwrap_or(max_key_len);
+        Ok(String::from_utf8_lossy(&key[..len]).into_owned())
+    }
+
+    /// Sends the periodic tick event. Like mosquitto 2.0 it leaves the time fields zero.
+    pub fn tick(&mut self) {
+        let mut event_data = mosquitto_evt_tick {
+            future: null_mut(),
+            now_ns: 0,
+            next_ns: 0,
+            now_s: 0,
+            next_s: 0,
+            future2: [null_mut(); 4],
+        };
+        self.fire_all(MosquittoPluginEvent::MosqEvtTick, &mut event_data);
+    }
+
+    /// Fires the tick event at `now` on the broker's monotonic clock, to step the time of
+    /// scheduled tasks
+    pub fn tick_at(&mut self, now: Duration) {
+        let mut event_data = mosquitto_evt_tick {
+            future: null_mut(),
+            now_ns: now.subsec_nanos() as _,
+            next_ns: 0,
+            now_s: now.as_secs() as _,
+            next_s: 0,
+            future2: [null_mut(); 4],
+        };
+        self.fire_all(MosquittoPluginEvent::MosqEvtTick, &mut event_data);
+    }
+
+    /// Tells the plugin that `client` disconnected
+    pub fn disconnect(&mut self, client: &FakeClient, reason: DisconnectReason) {
+        let mut event_data = mosquitto_evt_disconnect {
+            future: null_mut(),
+            client: client.as_ptr(),
+            reason: reason.into(),
+            future2: [null_mut(); 4],
+        };
+        self.fire_all(MosquittoPluginEvent::MosqEvtDisconnect, &mut event_data);
+    }
+
+    /// Tells the plugin that `client` connected, mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    pub fn connect(&mut self, client: &FakeClient) {
+        let mut event_data = mosquitto_evt_connect {
+            future: null_mut(),
+            client: client.as_ptr(),
+            future2: [null_mut(); 10],
+        };
+        self.fire_all(MosquittoPluginEvent::MosqEvtConnect, &mut event_data);
+    }
+
+    /// Subscribes `client` to `topic_filter` with the given MQTT subscription options.
+    /// Any callback failing denies the subscription. mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    pub fn subscribe(
+        &mut self,
+        client: &FakeClient,
+        topic_filter: &str,
+        options: u8,
+    ) -> Result<Success, Error> {
+        self.subscription(
+            MosquittoPluginEvent::MosqEvtSubscribe,
+            client,
+            topic_filter,
+            options,
+        )
+    }
+
+    /// Unsubscribes `client` from `topic_filter`, mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    pub fn unsubscribe(
+        &mut self,
+        client: &FakeClient,
+        topic_filter: &str,
+    ) -> Result<Success, Error> {
+        self.subscription(
+            MosquittoPluginEvent::MosqEvtUnsubscribe,
+            client,
+            topic_filter,
+            0,
+        )
+    }
+
+    #[cfg(feature = "mosq-2-1")]
+    fn subscription(
+        &mut self,
+        event: MosquittoPluginEvent,
+        client: &FakeClient,
+        topic_filter: &str,
+        options: u8,
+    ) -> Result<Success, Error> {
+        let topic_filter = c_string(topic_filter, "topic filter");
+        let mut event_data = mosquitto_evt_subscribe {
+            future: null_mut(),
+            client: client.as_ptr(),
+            data: mosquitto_subscription {
+                clientid: client
+                    .id
+                    .as_ref()
+                    .map_or(null_mut(), |id| id.as_ptr() as *mut c_char),
+                topic_filter: topic_filter.as_ptr() as *mut c_char,
+                properties: null_mut(),
+                identifier: 0,
+                options,
+                padding: [0; 3],
+                future2: [null_mut(); 8],
+            },
+            future2: [null_mut(); 8],
+        };
+        for (_, callback, userdata) in self.callbacks(event) {
+            let rc = unsafe {
+                callback(
+                    event as c_int,
+                    &mut event_data as *mut _ as *mut c_void,
+                    userdata,
+                )
+            };
+            if rc != 0 {
+                return result(rc);
+            }
+        }
+        Ok(Success)
+    }
+
+    /// Tells the plugin that the connection of `client` closed while its session lives on,
+    /// mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    pub fn client_offline(&mut self, client: &FakeClient, reason: DisconnectReason) {
+        let mut event_data = mosquitto_evt_client_offline {
+            future: null_mut(),
+            client: client.as_ptr(),
+            reason: reason.into(),
+            future2: [null_mut(); 4],
+        };
+        self.fire_all(MosquittoPluginEvent::MosqEvtClientOffline, &mut event_data);
+    }
+
+    /// The name and version the instance reported with mosquitto_plugin_set_info,
+    /// mosquitto 2.1 only
+    #[cfg(feature = "mosq-2-1")]
+    pub fn plugin_info(&self, id: PluginId) -> Option<(String, Option<String>)> {
+        let identifier = self
+            .plugins
+            .iter()
+            .find(|plugin| plugin.id == id)?
+            .identifier();
+        BROKER.with(|broker| broker.borrow().plugin_info.get(&identifier).cloned())
+    }
+
+    /// Makes the registration of callbacks for `event` fail with `error` from now on
+    pub fn fail_registrations(&mut self, event: MosquittoPluginEvent, error: Error) {
+        BROKER.with(|broker| {
+            let mut broker = broker.borrow_mut();
+            broker
+                .failing_registrations
+                .push((event as c_int, error.into()))
+        });
+    }
+
+    /// Messages published so far
+    pub fn published(&self) -> Vec<PublishedMessage> {
+        BROKER.with(|broker| broker.borrow().published.clone())
+    }
+
+    /// Messages published so far, clearing the record
+    pub fn take_published(&mut self) -> Vec<PublishedMessage> {
+        BROKER.with(|broker| std::mem::take(&mut broker.borrow_mut().published))
+    }
+
+    /// Clients kicked so far
+    pub fn kicks(&self) -> Vec<Kick> {
+        BROKER.with(|broker| broker.borrow().kicks.clone())
+    }
+
+    /// Messages logged so far
+    pub fn logs(&self) -> Vec<LogEntry> {
+        BROKER.with(|broker| broker.borrow().logs.clone())
+    }
+
+    /// The certificates the plugin got from mosquitto_client_certificate and didn't free yet
+    pub fn certificates_in_use(&self) -> usize {
+        BROKER.with(|broker| broker.borrow().certificates_in_use)
+    }
+
+    /// The events with a registered callback, in the order they were registered, including
+    /// those left behind by unloaded plugins
+    pub fn registered_events(&self) -> Vec<i32> {
+        BROKER.with(|broker| {
+            broker
+                .borrow()
+                .registrations
+                .iter()
+                .map(|r| r.event)
+                .collect()
+        })
+    }
+
+    // The callbacks for `event` with the identifier of their plugin, copied out so the plugin can
+    // call the mocks while they run
+    fn callbacks(
+        &self,
+        event: MosquittoPluginEvent,
+    ) -> Vec<(*mut mosquitto_plugin_id_t, Callback, *mut c_void)> {
+        let event = event as c_int;
+        let registrations: Vec<_> = BROKER.with(|broker| {
+            broker
+                .borrow()
+                .registrations
+                .iter()
+                .filter(|r| r.event == event)
+                .map(|r| (r.identifier, r.callback, r.userdata))
+                .collect()
+        });
+        registrations
+            .into_iter()
+            .map(|(identifier, callback, userdata)| {
+                self.assert_loaded(identifier, event);
+                (identifier, callback, userdata)
+            })
+            .collect()
+    }
+
+    fn assert_loaded(&self, identifier: *mut mosquitto_plugin_id_t, event: c_int) {
+        let unloaded = self
+            .unloaded
+            .iter()
+            .any(|id| id.as_ptr() as *mut mosquitto_plugin_id_t == identifier);
+        assert!(
+            !unloaded,
+            "callback for event {} is still registered by an unloaded plugin",
+            event
+        );
+    }
+
+    // Calls every callback, the way the broker handles notifications
+    fn fire_all<T>(&mut self, event: MosquittoPluginEvent, event_data: &mut T) {
+        let id = event as c_int;
+        for (_, callback, userdata) in self.callbacks(event) {
+            unsafe { callback(id, event_data as *mut T as *mut c_void, userdata) };
+        }
+    }
+
+    // Calls the callbacks until one of them doesn't defer, the way the broker chains
+    // authentication and access checks
+    fn fire_until_decided<T>(&mut self, event: MosquittoPluginEvent, event_data: &mut T) -> c_int {
+        let id = event as c_int;
+        for (_, callback, userdata) in self.callbacks(event) {
+            let rc = unsafe { callback(id, event_data as *mut T as *mut c_void, userdata) };
+            if rc != MOSQ_ERR_PLUGIN_DEFER {
+                return rc;
+            }
+        }
+        MOSQ_ERR_PLUGIN_DEFER
+    }
+}
+
+impl Default for MockBroker {
+    fn default() -> Self {
+        MockBroker::new()
+    }
+}
+
+impl Drop for MockBroker {
+    fn drop(&mut self) {
+        while let Some(plugin) = self.plugins.last() {
+            let _ = self.unload_plugin(plugin.id);
+        }
+    }
+}
@@ -0,0 +1,851 @@
+// Rust implementations of the mosquitto broker functions the crate calls into.
+//
+// They replace the symbols the broker would export to a loaded plugin, and record what the
+// plugin did in the thread local broker state, so a test binary links and runs without
+// mosquitto. Memory handed across uses the C allocator, like the broker does.
+
+use super::client::FakeClient;
+use super::{c_string, Kick, LogEntry, PublishedMessage};
+use mosquitto_plugin::libc;
+use mosquitto_plugin::mosquitto_dev::{mosquitto, mosquitto_plugin_id_t, mosquitto_property};
+use mosquitto_plugin::properties::{
+    OwnedProperties, Properties, MQTT_PROP_CONTENT_TYPE, MQTT_PROP_CORRELATION_DATA,
+    MQTT_PROP_MESSAGE_EXPIRY_INTERVAL, MQTT_PROP_RESPONSE_TOPIC, MQTT_PROP_USER_PROPERTY,
+};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr::{null, null_mut};
+
+const MOSQ_ERR_SUCCESS: c_int = 0;
+const MOSQ_ERR_NOMEM: c_int = 1;
+const MOSQ_ERR_INVAL: c_int = 3;
+const MOSQ_ERR_NOT_FOUND: c_int = 6;
+const MOSQ_ERR_ALREADY_EXISTS: c_int = 31;
+
+pub(crate) type Callback = unsafe extern "C" fn(c_int, *mut c_void, *mut c_void) -> c_int;
+
+pub(crate) struct Registration {
+    pub identifier: *mut mosquitto_plugin_id_t,
+    pub event: c_int,
+    pub callback: Callback,
+    // The control topic for control callbacks, copied like the broker does
+    pub topic: Option<CString>,
+    pub userdata: *mut c_void,
+}
+
+#[derive(Default)]
+pub(crate) struct BrokerState {
+    pub registrations: Vec<Registration>,
+    // Events whose registration fails, with the code returned
+    pub failing_registrations: Vec<(c_int, c_int)>,
+    pub published: Vec<PublishedMessage>,
+    pub kicks: Vec<Kick>,
+    pub logs: Vec<LogEntry>,
+    // Certificates handed out by mosquitto_client_certificate and not freed yet
+    pub certificates_in_use: usize,
+    #[cfg(feature = "mosq-2-1")]
+    pub plugin_info:
+        std::collections::HashMap<*mut mosquitto_plugin_id_t, (String, Option<String>)>,
+}
+
+thread_local! {
+    pub(crate) static BROKER: RefCell<BrokerState> = RefCell::new(BrokerState::default());
+}
+
+unsafe fn string(s: *const c_char) -> Option<String> {
+    (!s.is_null()).then(|| CStr::from_ptr(s).to_string_lossy().into_owned())
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_callback_register(
+    identifier: *mut mosquitto_plugin_id_t,
+    event: c_int,
+    cb_func: Option<Callback>,
+    event_data: *const c_void,
+    userdata: *mut c_void,
+) -> c_int {
+    let callback = match cb_func {
+        Some(callback) => callback,
+        None => return MOSQ_ERR_INVAL,
+    };
+    let topic = if event == mosquitto_plugin::MosquittoPluginEvent::MosqEvtControl as c_int {
+        // Control callbacks are keyed by their topic, which has to be below $CONTROL/
+        let topic = unsafe { string(event_data as *const c_char) };
+        match topic {
+            Some(topic) if topic.starts_with("$CONTROL/") => Some(CString::new(topic).unwrap()),
+            _ => return MOSQ_ERR_INVAL,
+        }
+    } else {
+        None
+    };
+
+    BROKER.with(|broker| {
+        let mut broker = broker.borrow_mut();
+        let failing = broker
+            .failing_registrations
+            .iter()
+            .find(|(e, _)| *e == event);
+        if let Some((_, rc)) = failing {
+            return *rc;
+        }
+        // Each plugin instance has its own callbacks, as with per_listener_settings
+        let exists = broker.registrations.iter().any(|r| {
+            r.identifier == identifier
+                && r.event == event
+                && if topic.is_some() {
+                    r.topic == topic
+                } else {
+                    r.callback as usize == callback as usize
+                }
+        });
+        if exists {
+            return MOSQ_ERR_ALREADY_EXISTS;
+        }
+        broker.registrations.push(Registration {
+            identifier,
+            event,
+            callback,
+            topic,
+            userdata,
+        });
+        MOSQ_ERR_SUCCESS
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_callback_unregister(
+    identifier: *mut mosquitto_plugin_id_t,
+    event: c_int,
+    cb_func: Option<Callback>,
+    event_data: *const c_void,
+) -> c_int {
+    let callback = match cb_func {
+        Some(callback) => callback,
+        None => return MOSQ_ERR_INVAL,
+    };
+    let topic = if event == mosquitto_plugin::MosquittoPluginEvent::MosqEvtControl as c_int {
+        unsafe { string(event_data as *const c_char) }.map(|topic| CString::new(topic).unwrap())
+    } else {
+        None
+    };
+
+    BROKER.with(|broker| {
+        let mut broker = broker.borrow_mut();
+        let position = broker.registrations.iter().position(|r| {
+            r.identifier == identifier
+                && r.event == event
+                && r.callback as usize == callback as usize
+                && r.topic == topic
+        });
+        match position {
+            Some(position) => {
+                broker.registrations.remove(position);
+                MOSQ_ERR_SUCCESS
+            }
+            None => MOSQ_ERR_NOT_FOUND,
+        }
+    })
+}
+
+#[cfg(feature = "mosq-2-1")]
+#[no_mangle]
+pub extern "C" fn mosquitto_plugin_set_info(
+    identifier: *mut mosquitto_plugin_id_t,
+    plugin_name: *const c_char,
+    plugin_version: *const c_char,
+) -> c_int {
+    let name = match unsafe { string(plugin_name) } {
+        Some(name) => name,
+        None => return MOSQ_ERR_INVAL,
+    };
+    let version = unsafe { string(plugin_version) };
+    BROKER.with(|broker| {
+        broker
+            .borrow_mut()
+            .plugin_info
+            .insert(identifier, (name, version));
+    });
+    MOSQ_ERR_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_malloc(size: usize) -> *mut c_void {
+    unsafe { libc::malloc(size) }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_calloc(nmemb: usize, size: usize) -> *mut c_void {
+    unsafe { libc::calloc(nmemb, size) }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    unsafe { libc::realloc(ptr, size) }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_free(mem: *mut c_void) {
+    unsafe { libc::free(mem) }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_strdup(s: *const c_char) -> *mut c_char {
+    unsafe { libc::strdup(s) }
+}
+
+// The broker function is variadic, which can't be defined in stable rust. This takes the single
+// argument the crate passes along with its "%s" format, and records any other format verbatim.
+#[no_mangle]
+pub extern "C" fn mosquitto_log_printf(level: c_int, fmt: *const c_char, message: *const c_char) {
+    let fmt = unsafe { string(fmt) }.unwrap_or_default();
+    let message = if fmt == "%s" {
+        unsafe { string(message) }.unwrap_or_default()
+    } else {
+        fmt
+    };
+    BROKER.with(|broker| {
+        broker.borrow_mut().logs.push(LogEntry { level, message });
+    });
+}
+
+unsafe fn client<'a>(client: *const mosquitto) -> &'a FakeClient {
+    debug_assert!(!client.is_null(), "mock broker called without client");
+    &*(client as *const FakeClient)
+}
+
+fn c_str_or_null(s: Option<&CString>) -> *const c_char {
+    s.map_or(null(), |s| s.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_address(client: *const mosquitto) -> *const c_char {
+    c_str_or_null(unsafe { self::client(client) }.address.as_ref())
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_clean_session(client: *const mosquitto) -> bool {
+    unsafe { self::client(client) }.clean_session
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_id(client: *const mosquitto) -> *const c_char {
+    c_str_or_null(unsafe { self::client(client) }.id.as_ref())
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_keepalive(client: *const mosquitto) -> c_int {
+    unsafe { self::client(client) }.keepalive
+}
+
+// Stands in for OpenSSL's X509, which the broker hands out with a reference the caller releases
+struct MockX509 {
+    der: Vec<u8>,
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_certificate(client: *const mosquitto) -> *mut c_void {
+    match &unsafe { self::client(client) }.certificate {
+        Some(der) => {
+            BROKER.with(|broker| broker.borrow_mut().certificates_in_use += 1);
+            Box::into_raw(Box::new(MockX509 { der: der.clone() })) as *mut c_void
+        }
+        None => null_mut(),
+    }
+}
+
+// Only the two calls the crate makes are supported: asking for the length with a null `out`, and
+// writing to the buffer `*out` points at, moving it past the encoding
+// Handed to the crate in place of libcrypto's i2d_X509 and X509_free, which it looks up at runtime
+pub(crate) extern "C" fn i2d_x509(x509: *mut c_void, out: *mut *mut u8) -> c_int {
+    let der = &unsafe { &*(x509 as *const MockX509) }.der;
+    if !out.is_null() {
+        unsafe {
+            if (*out).is_null() {
+                return -1;
+            }
+            std::ptr::copy_nonoverlapping(der.as_ptr(), *out, der.len());
+            *out = (*out).add(der.len());
+        }
+    }
+    der.len() as c_int
+}
+
+pub(crate) extern "C" fn x509_free(x509: *mut c_void) {
+    if !x509.is_null() {
+        drop(unsafe { Box::from_raw(x509 as *mut MockX509) });
+        BROKER.with(|broker| broker.borrow_mut().certificates_in_use -= 1);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_protocol(client: *const mosquitto) -> c_int {
+    unsafe { self::client(client) }.protocol
+}
+
+#[cfg(feature = "mosq-2-1")]
+#[no_mangle]
+pub extern "C" fn mosquitto_client_port(client: *const mosquitto) -> c_int {
+    unsafe { self::client(client) }.port
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_protocol_version(client: *const mosquitto) -> c_int {
+    unsafe { self::client(client) }.protocol_version
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_sub_count(client: *const mosquitto) -> c_int {
+    unsafe { self::client(client) }.sub_count
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_client_username(client: *const mosquitto) -> *const c_char {
+    // The pointer stays valid until the next set_username, as with the broker
+    let client = unsafe { self::client(client) };
+    c_str_or_null(client.username.borrow().as_ref())
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_set_username(client: *mut mosquitto, username: *const c_char) -> c_int {
+    let client = unsafe { self::client(client) };
+    let username = (!username.is_null()).then(|| unsafe { CStr::from_ptr(username) }.to_owned());
+    client.replace_username(username);
+    MOSQ_ERR_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_kick_client_by_clientid(
+    clientid: *const c_char,
+    with_will: bool,
+) -> c_int {
+    let kick = match unsafe { string(clientid) } {
+        Some(client_id) => Kick::ClientId {
+            client_id,
+            with_will,
+        },
+        None => Kick::All { with_will },
+    };
+    BROKER.with(|broker| broker.borrow_mut().kicks.push(kick));
+    MOSQ_ERR_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_kick_client_by_username(
+    username: *const c_char,
+    with_will: bool,
+) -> c_int {
+    let username = match unsafe { string(username) } {
+        Some(username) => username,
+        None => return MOSQ_ERR_INVAL,
+    };
+    BROKER.with(|broker| {
+        broker.borrow_mut().kicks.push(Kick::Username {
+            username,
+            with_will,
+        })
+    });
+    MOSQ_ERR_SUCCESS
+}
+
+// Records a publish. Ownership of the properties passes to the broker on success only.
+fn publish(
+    clientid: *const c_char,
+    topic: *const c_char,
+    payloadlen: c_int,
+    payload: *const c_void,
+    qos: c_int,
+    retain: bool,
+    mut properties: *mut mosquitto_property,
+) -> c_int {
+    if topic.is_null() || payloadlen < 0 || (payloadlen > 0 && payload.is_null()) {
+        return MOSQ_ERR_INVAL;
+    }
+    if !(0..=2).contains(&qos) {
+        return MOSQ_ERR_INVAL;
+    }
+
+    let payload = if payloadlen > 0 {
+        unsafe { std::slice::from_raw_parts(payload as *const u8, payloadlen as usize) }.to_vec()
+    } else {
+        Vec::new()
+    };
+    let view = unsafe { Properties::from_ptr(properties) };
+    let published = PublishedMessage {
+        client_id: unsafe { string(clientid) },
+        topic: unsafe { string(topic) }.unwrap_or_default(),
+        payload,
+        qos,
+        retain,
+        user_properties: view.user_properties(),
+        content_type: view.content_type(),
+        response_topic: view.response_topic(),
+        correlation_data: view.correlation_data(),
+        message_expiry_interval: view.message_expiry_interval(),
+    };
+    mosquitto_property_free_all(&mut properties);
+
+    BROKER.with(|broker| broker.borrow_mut().published.push(published));
+    MOSQ_ERR_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_broker_publish(
+    clientid: *const c_char,
+    topic: *const c_char,
+    payloadlen: c_int,
+    payload: *mut c_void,
+    qos: c_int,
+    retain: bool,
+    properties: *mut mosquitto_property,
+) -> c_int {
+    let res = publish(
+        clientid, topic, payloadlen, payload, qos, retain, properties,
+    );
+    if res == MOSQ_ERR_SUCCESS {
+        mosquitto_free(payload);
+    }
+    res
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_broker_publish_copy(
+    clientid: *const c_char,
+    topic: *const c_char,
+    payloadlen: c_int,
+    payload: *const c_void,
+    qos: c_int,
+    retain: bool,
+    properties: *mut mosquitto_property,
+) -> c_int {
+    publish(
+        clientid, topic, payloadlen, payload, qos, retain, properties,
+    )
+}
+
+// Property lists are singly linked lists of these. The `mosquitto_property` pointers handed out
+// point to them.
+pub(crate) struct MockProperty {
+    identifier: c_int,
+    value: PropertyValue,
+    next: *mut MockProperty,
+}
+
+#[derive(Clone)]
+enum PropertyValue {
+    Byte(u8),
+    Int16(u16),
+    Int32(u32),
+    Varint(u32),
+    Binary(Vec<u8>),
+    String(CString),
+    StringPair(CString, CString),
+}
+
+fn add_property(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    value: PropertyValue,
+) -> c_int {
+    if proplist.is_null() {
+        return MOSQ_ERR_INVAL;
+    }
+    let property = Box::into_raw(Box::new(MockProperty {
+        identifier,
+        value,
+        next: null_mut(),
+    }));
+    unsafe {
+        let mut tail = proplist as *mut *mut MockProperty;
+        while !(*tail).is_null() {
+            tail = &mut (**tail).next;
+        }
+        *tail = property;
+    }
+    MOSQ_ERR_SUCCESS
+}
+
+// The property list a client would have sent along with a message, freed with
+// mosquitto_property_free_all
+pub(crate) fn property_list(properties: &OwnedProperties) -> *mut mosquitto_property {
+    let mut list = null_mut();
+    for (name, value) in &properties.user_properties {
+        let name = c_string(name, "user property name");
+        let value = c_string(value, "user property value");
+        add_property(
+            &mut list,
+            MQTT_PROP_USER_PROPERTY,
+            PropertyValue::StringPair(name, value),
+        );
+    }
+    if let Some(content_type) = &properties.content_type {
+        let content_type = c_string(content_type, "content type");
+        add_property(
+            &mut list,
+            MQTT_PROP_CONTENT_TYPE,
+            PropertyValue::String(content_type),
+        );
+    }
+    if let Some(response_topic) = &properties.response_topic {
+        let response_topic = c_string(response_topic, "response topic");
+        add_property(
+            &mut list,
+            MQTT_PROP_RESPONSE_TOPIC,
+            PropertyValue::String(response_topic),
+        );
+    }
+    if let Some(correlation_data) = &properties.correlation_data {
+        let correlation_data = PropertyValue::Binary(correlation_data.clone());
+        add_property(&mut list, MQTT_PROP_CORRELATION_DATA, correlation_data);
+    }
+    if let Some(seconds) = properties.message_expiry_interval {
+        let seconds = PropertyValue::Int32(seconds);
+        add_property(&mut list, MQTT_PROP_MESSAGE_EXPIRY_INTERVAL, seconds);
+    }
+    list
+}
+
+unsafe fn owned_c_str(s: *const c_char) -> Option<CString> {
+    (!s.is_null()).then(|| CStr::from_ptr(s).to_owned())
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_add_byte(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    value: u8,
+) -> c_int {
+    add_property(proplist, identifier, PropertyValue::Byte(value))
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_add_int16(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    value: u16,
+) -> c_int {
+    add_property(proplist, identifier, PropertyValue::Int16(value))
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_add_int32(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    value: u32,
+) -> c_int {
+    add_property(proplist, identifier, PropertyValue::Int32(value))
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_add_varint(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    value: u32,
+) -> c_int {
+    add_property(proplist, identifier, PropertyValue::Varint(value))
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_add_binary(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    value: *const c_void,
+    len: u16,
+) -> c_int {
+    if value.is_null() && len > 0 {
+        return MOSQ_ERR_INVAL;
+    }
+    let value = if len > 0 {
+        unsafe { std::slice::from_raw_parts(value as *const u8, len as usize) }.to_vec()
+    } else {
+        Vec::new()
+    };
+    add_property(proplist, identifier, PropertyValue::Binary(value))
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_add_string(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    value: *const c_char,
+) -> c_int {
+    match unsafe { owned_c_str(value) } {
+        Some(value) => add_property(proplist, identifier, PropertyValue::String(value)),
+        None => MOSQ_ERR_INVAL,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_add_string_pair(
+    proplist: *mut *mut mosquitto_property,
+    identifier: c_int,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    match unsafe { (owned_c_str(name), owned_c_str(value)) } {
+        (Some(name), Some(value)) => {
+            add_property(proplist, identifier, PropertyValue::StringPair(name, value))
+        }
+        _ => MOSQ_ERR_INVAL,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_identifier(property: *const mosquitto_property) -> c_int {
+    if property.is_null() {
+        return 0;
+    }
+    unsafe { (*(property as *const MockProperty)).identifier }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_next(
+    proplist: *const mosquitto_property,
+) -> *const mosquitto_property {
+    if proplist.is_null() {
+        return null();
+    }
+    unsafe { (*(proplist as *const MockProperty)).next as *const mosquitto_property }
+}
+
+// Finds the property a read function returns, honouring skip_first like the broker does
+fn find_property(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    skip_first: bool,
+) -> Option<&'static MockProperty> {
+    let mut property = proplist as *const MockProperty;
+    if skip_first && !property.is_null() {
+        property = unsafe { (*property).next };
+    }
+    while !property.is_null() {
+        let current = unsafe { &*property };
+        if current.identifier == identifier {
+            return Some(current);
+        }
+        property = current.next;
+    }
+    None
+}
+
+fn as_property(property: &MockProperty) -> *const mosquitto_property {
+    property as *const MockProperty as *const mosquitto_property
+}
+
+unsafe fn copy_bytes(bytes: &[u8], nul_terminated: bool) -> *mut c_void {
+    let len = bytes.len() + nul_terminated as usize;
+    let copy = libc::calloc(1, len.max(1));
+    if !copy.is_null() {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), copy as *mut u8, bytes.len());
+    }
+    copy
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_read_byte(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    value: *mut u8,
+    skip_first: bool,
+) -> *const mosquitto_property {
+    match find_property(proplist, identifier, skip_first) {
+        Some(
+            property @ MockProperty {
+                value: PropertyValue::Byte(byte),
+                ..
+            },
+        ) => {
+            if !value.is_null() {
+                unsafe { *value = *byte };
+            }
+            as_property(property)
+        }
+        _ => null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_read_int16(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    value: *mut u16,
+    skip_first: bool,
+) -> *const mosquitto_property {
+    match find_property(proplist, identifier, skip_first) {
+        Some(
+            property @ MockProperty {
+                value: PropertyValue::Int16(int),
+                ..
+            },
+        ) => {
+            if !value.is_null() {
+                unsafe { *value = *int };
+            }
+            as_property(property)
+        }
+        _ => null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_read_int32(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    value: *mut u32,
+    skip_first: bool,
+) -> *const mosquitto_property {
+    match find_property(proplist, identifier, skip_first) {
+        Some(
+            property @ MockProperty {
+                value: PropertyValue::Int32(int),
+                ..
+            },
+        ) => {
+            if !value.is_null() {
+                unsafe { *value = *int };
+            }
+            as_property(property)
+        }
+        _ => null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_read_varint(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    value: *mut u32,
+    skip_first: bool,
+) -> *const mosquitto_property {
+    match find_property(proplist, identifier, skip_first) {
+        Some(
+            property @ MockProperty {
+                value: PropertyValue::Varint(int),
+                ..
+            },
+        ) => {
+            if !value.is_null() {
+                unsafe { *value = *int };
+            }
+            as_property(property)
+        }
+        _ => null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_read_binary(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    value: *mut *mut c_void,
+    len: *mut u16,
+    skip_first: bool,
+) -> *const mosquitto_property {
+    match find_property(proplist, identifier, skip_first) {
+        Some(
+            property @ MockProperty {
+                value: PropertyValue::Binary(bytes),
+                ..
+            },
+        ) => {
+            if !value.is_null() && !len.is_null() {
+                unsafe {
+                    *value = copy_bytes(bytes, false);
+                    *len = bytes.len() as u16;
+                }
+            }
+            as_property(property)
+        }
+        _ => null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_read_string(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    value: *mut *mut c_char,
+    skip_first: bool,
+) -> *const mosquitto_property {
+    match find_property(proplist, identifier, skip_first) {
+        Some(
+            property @ MockProperty {
+                value: PropertyValue::String(string),
+                ..
+            },
+        ) => {
+            if !value.is_null() {
+                unsafe { *value = copy_bytes(string.as_bytes(), true) as *mut c_char };
+            }
+            as_property(property)
+        }
+        _ => null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_read_string_pair(
+    proplist: *const mosquitto_property,
+    identifier: c_int,
+    name: *mut *mut c_char,
+    value: *mut *mut c_char,
+    skip_first: bool,
+) -> *const mosquitto_property {
+    match find_property(proplist, identifier, skip_first) {
+        Some(
+            property @ MockProperty {
+                value: PropertyValue::StringPair(n, v),
+                ..
+            },
+        ) => {
+            unsafe {
+                if !name.is_null() {
+                    *name = copy_bytes(n.as_bytes(), true) as *mut c_char;
+                }
+                if !value.is_null() {
+                    *value = copy_bytes(v.as_bytes(), true) as *mut c_char;
+                }
+            }
+            as_property(property)
+        }
+        _ => null(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_free_all(properties: *mut *mut mosquitto_property) {
+    if properties.is_null() {
+        return;
+    }
+    unsafe {
+        let mut property = *properties as *mut MockProperty;
+        while !property.is_null() {
+            let next = (*property).next;
+            drop(Box::from_raw(property));
+            property = next;
+        }
+        *properties = null_mut();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mosquitto_property_copy_all(
+    dest: *mut *mut mosquitto_property,
+    src: *const mosquitto_property,
+) -> c_int {
+    if dest.is_null() {
+        return MOSQ_ERR_INVAL;
+    }
+    unsafe { *dest = null_mut() };
+    let mut property = src as *const MockProperty;
+    while !property.is_null() {
+        let current = unsafe { &*property };
+        let res = add_property(dest, current.identifier, current.value.clone());
+        if res != MOSQ_ERR_SUCCESS {
+            mosquitto_property_free_all(dest);
+            return MOSQ_ERR_NOMEM;
+        }
+        property = current.next;
+    }
+    MOSQ_ERR_SUCCESS
+}
@@ -0,0 +1,817 @@
+// Drives a plugin using every part of the API through the mock broker, which tests the
+// generated callbacks of create_dynamic_library! as much as the mocks.
+
+use mosquitto_plugin::*;
+use mosquitto_plugin_testing::{FakeClient, Kick, MockBroker};
+use std::time::Duration;
+
+#[derive(Debug)]
+struct Plugin {
+    topic: String,
+    ticks: u32,
+    scheduler: scheduler::Scheduler<Plugin>,
+}
+
+const EVENTS_2_0: EventSet = EventSet::RELOAD
+    .union(EventSet::ACL_CHECK)
+    .union(EventSet::CONTROL)
+    .union(EventSet::BASIC_AUTH)
+    .union(EventSet::EXT_AUTH)
+    .union(EventSet::MESSAGE)
+    .union(EventSet::PSK_KEY)
+    .union(EventSet::TICK)
+    .union(EventSet::DISCONNECT);
+
+impl MosquittoPlugin for Plugin {
+    #[cfg(feature = "mosq-2-0")]
+    const EVENTS: EventSet = EVENTS_2_0;
+    #[cfg(feature = "mosq-2-1")]
+    const EVENTS: EventSet = EVENTS_2_0.union(EventSet::SUBSCRIBE);
+    const RELOAD_POLICY: ReloadPolicy = ReloadPolicy::Rebuild;
+    const CONTROL_TOPICS: &'static [&'static str] = &[
+        "$CONTROL/test-plugin/v1",
+        "$CONTROL/test-plugin/v2",
+        "not-control",
+    ];
+
+    fn init(opts: MosquittoOpt) -> Result<Self, InitError> {
+        let topic = opts.get("topic").unwrap_or(&"allowed");
+        if topic.is_empty() {
+            return Err(InitError::invalid_options("the topic is empty"));
+        }
+        // Publishes the topic every `heartbeat` seconds
+        let mut scheduler = scheduler::Scheduler::new();
+        if let Some(heartbeat) = opts.get("heartbeat") {
+            let interval = heartbeat
+                .parse()
+                .map_err(|_| InitError::invalid_options("the heartbeat is not a number"))?;
+            scheduler.schedule_every(Duration::from_secs(interval), |plugin: &mut Plugin| {
+                mosquitto_calls::publish_broadcast(
+                    "heartbeat",
+                    plugin.topic.as_bytes(),
+                    QOS::AtMostOnce,
+                    false,
+                )
+                .unwrap();
+            });
+        }
+        Ok(Plugin {
+            topic: topic.to_string(),
+            ticks: 0,
+            scheduler,
+        })
+    }
+
+    fn scheduler(&mut self) -> Option<&mut scheduler::Scheduler<Self>> {
+        Some(&mut self.scheduler)
+    }
+
+    fn on_control(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        message: MosquittoMessage,
+    ) -> Option<Vec<u8>> {
+        match message.payload {
+            b"ping" => Some(format!("pong from {}", message.topic).into_bytes()),
+            _ => None,
+        }
+    }
+
+    fn on_psk(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _hint: &std::ffi::CStr,
+        identity: &std::ffi::CStr,
+    ) -> Option<PskKey> {
+        match identity.to_bytes() {
+            b"sensor" => PskKey::from_hex("deadbeef"),
+            b"oversized" => Some(PskKey::from_bytes(vec![0; 64])),
+            _ => None,
+        }
+    }
+
+    fn acl_check(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        listener: Option<Listener>,
+        _acl: AclCheckAccessLevel,
+        msg: MosquittoMessage,
+    ) -> Result<Success, Error> {
+        let internal = listener.and_then(|listener| listener.port) == Some(1884);
+        let admin = msg.properties.user_property("role").as_deref() == Some("admin");
+        if msg.topic == self.topic || internal || admin {
+            Ok(Success)
+        } else {
+            mosquitto_warn!("denied {}", msg.topic);
+            Err(Error::AclDenied)
+        }
+    }
+
+    fn username_password(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        listener: Option<Listener>,
+        username: Option<&[u8]>,
+        password: Option<&[u8]>,
+    ) -> Result<Success, Error> {
+        let internal = listener.and_then(|listener| listener.port) == Some(1884);
+        match (username, password) {
+            (Some(b"panic"), _) => panic!("asked to panic"),
+            (Some(b"operator"), None) if internal => Ok(Success),
+            (Some(b"user"), Some(b"pass")) => Ok(Success),
+            (Some(b"binary"), Some(b"\xff\xfe")) => Ok(Success),
+            (Some(b"us\xffer"), Some(b"pass")) => Ok(Success),
+            (None, None) => Err(Error::PluginDefer),
+            _ => Err(Error::Auth),
+        }
+    }
+
+    fn on_auth_start(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
+        auth: AuthStart,
+    ) -> AuthOutcome {
+        match auth.method {
+            Some("echo") if auth.reauth => AuthOutcome::Accept(Some(b"welcome back".to_vec())),
+            Some("echo") => AuthOutcome::Continue(auth.data.unwrap_or_default().to_vec()),
+            Some("oversized") => AuthOutcome::Continue(vec![0; 65536]),
+            _ => AuthOutcome::Deny,
+        }
+    }
+
+    fn on_auth_continue(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
+        _method: Option<&str>,
+        data: Option<&[u8]>,
+    ) -> AuthOutcome {
+        match data {
+            Some(b"challenge") => AuthOutcome::Accept(Some(b"welcome".to_vec())),
+            _ => AuthOutcome::Deny,
+        }
+    }
+
+    fn on_message(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        message: MosquittoMessage,
+    ) -> MessageAction {
+        match message.topic {
+            "drop" => MessageAction::Drop,
+            "shout" => {
+                let mut message = message.into_owned();
+                message.topic = "shouted".to_string();
+                message.payload.make_ascii_uppercase();
+                MessageAction::Replace(message)
+            }
+            "invalid" => {
+                let mut message = message.into_owned();
+                message.topic = "in\0valid".to_string();
+                MessageAction::Replace(message)
+            }
+            "annotate" => {
+                let mut properties = message.properties.into_owned();
+                properties
+                    .user_properties
+                    .push(("annotated-by".to_string(), "plugin".to_string()));
+                properties.content_type = Some("application/json".to_string());
+                let mut message = message.into_owned();
+                message.properties = Some(properties);
+                MessageAction::Replace(message)
+            }
+            "qos3" => {
+                let mut message = message.into_owned();
+                message.qos = 3;
+                MessageAction::Replace(message)
+            }
+            _ => MessageAction::Pass,
+        }
+    }
+
+    fn on_tick(&mut self, _now_ns: i64, _next_ns: i64, _now_s: i32, _next_s: i32) {
+        self.ticks += 1;
+        if self.ticks == 2 {
+            mosquitto_calls::kick_client_by_username("user", true).unwrap();
+        }
+    }
+
+    fn cleanup(&mut self, opts: MosquittoOpt) {
+        let topic = opts.get("topic").unwrap_or(&"");
+        mosquitto_calls::publish_broadcast("unloaded", topic.as_bytes(), QOS::AtMostOnce, false)
+            .unwrap();
+    }
+
+    fn on_disconnect(&mut self, client: &dyn MosquittoClientContext, reason: DisconnectReason) {
+        let id = client.get_id().unwrap_or_default();
+        let topic = if reason.is_clean() { "left" } else { "lost" };
+        mosquitto_calls::publish_broadcast(topic, id.as_bytes(), QOS::AtMostOnce, false).unwrap();
+    }
+
+    #[cfg(feature = "mosq-2-1")]
+    const PLUGIN_NAME: Option<&'static str> = Some("test-plugin");
+
+    #[cfg(feature = "mosq-2-1")]
+    fn on_subscribe(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        subscription: Subscription,
+    ) -> Result<Success, Error> {
+        if subscription.topic_filter == "#" || subscription.qos() == 2 {
+            Err(Error::AclDenied)
+        } else {
+            Ok(Success)
+        }
+    }
+}
+
+create_dynamic_library!(Plugin);
+
+fn message(topic: &str, payload: &[u8]) -> OwnedMessage {
+    OwnedMessage {
+        topic: topic.to_string(),
+        payload: payload.to_vec(),
+        qos: 1,
+        retain: false,
+        properties: None,
+    }
+}
+
+#[test]
+fn checks_access_with_the_loaded_options() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[("topic", "sensors")]).unwrap();
+    let client = FakeClient::new("client");
+
+    let allowed = message("sensors", b"21.5");
+    let denied = message("actuators", b"on");
+    assert_eq!(
+        broker.acl_check(&client, AclCheckAccessLevel::Write, &allowed),
+        Ok(Success)
+    );
+    assert_eq!(
+        broker.acl_check(&client, AclCheckAccessLevel::Write, &denied),
+        Err(Error::AclDenied)
+    );
+    assert!(broker
+        .logs()
+        .iter()
+        .any(|log| log.level == LOG_WARNING && log.message == "denied actuators"));
+}
+
+#[test]
+fn checks_access_with_the_properties_of_the_message() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[("topic", "sensors")]).unwrap();
+    let client = FakeClient::new("client");
+    let mut message = message("actuators", b"on");
+    message.properties = Some(OwnedProperties {
+        user_properties: vec![
+            ("role".to_string(), "admin".to_string()),
+            ("role".to_string(), "operator".to_string()),
+        ],
+        ..OwnedProperties::default()
+    });
+
+    assert_eq!(
+        broker.acl_check(&client, AclCheckAccessLevel::Write, &message),
+        Ok(Success)
+    );
+}
+
+#[test]
+fn chains_basic_auth() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    assert_eq!(
+        broker.basic_auth(&client, Some(b"user"), Some(b"pass")),
+        Ok(Success)
+    );
+    assert_eq!(
+        broker.basic_auth(&client, Some(b"user"), Some(b"wrong")),
+        Err(Error::Auth)
+    );
+    assert_eq!(
+        broker.basic_auth(&client, None, None),
+        Err(Error::PluginDefer)
+    );
+}
+
+#[test]
+fn handles_credentials_that_are_not_utf8() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    assert_eq!(
+        broker.basic_auth(&client, Some(b"binary"), Some(b"\xff\xfe")),
+        Ok(Success)
+    );
+    assert_eq!(
+        broker.basic_auth(&client, Some(b"us\xffer"), Some(b"pass")),
+        Ok(Success)
+    );
+}
+
+#[test]
+fn denies_and_logs_on_panic() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    assert_eq!(
+        broker.basic_auth(&client, Some(b"panic"), None),
+        Err(Error::Auth)
+    );
+    assert!(broker.logs().iter().any(|log| log.level == LOG_ERR
+        && log.message == "plugin panicked in username_password: asked to panic"));
+}
+
+#[test]
+fn passes_replaces_and_drops_messages() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    let passed = message("sensors", b"21.5");
+    assert_eq!(broker.message(&client, &passed), Ok(passed.clone()));
+    assert_eq!(
+        broker.message(&client, &message("shout", b"hello")),
+        Ok(message("shouted", b"HELLO"))
+    );
+    assert_eq!(
+        broker.message(&client, &message("drop", b"")),
+        Err(Error::AclDenied)
+    );
+}
+
+#[test]
+fn replaces_the_properties_of_messages() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+    let mut original = message("annotate", b"{}");
+    original.properties = Some(OwnedProperties {
+        user_properties: vec![("origin".to_string(), "sensor".to_string())],
+        content_type: Some("text/plain".to_string()),
+        correlation_data: Some(b"42".to_vec()),
+        message_expiry_interval: Some(60),
+        ..OwnedProperties::default()
+    });
+
+    let delivered = broker.message(&client, &original).unwrap();
+    assert_eq!(
+        delivered.properties,
+        Some(OwnedProperties {
+            user_properties: vec![
+                ("origin".to_string(), "sensor".to_string()),
+                ("annotated-by".to_string(), "plugin".to_string()),
+            ],
+            content_type: Some("application/json".to_string()),
+            response_topic: None,
+            correlation_data: Some(b"42".to_vec()),
+            message_expiry_interval: Some(60),
+        })
+    );
+
+    // Without properties set on the replacement, those of the message are kept
+    let mut shout = message("shout", b"hello");
+    shout.properties = original.properties.clone();
+    let mut shouted = message("shouted", b"HELLO");
+    shouted.properties = original.properties;
+    assert_eq!(broker.message(&client, &shout), Ok(shouted));
+}
+
+#[test]
+fn passes_messages_on_unchanged_if_the_replacement_is_invalid() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    for topic in ["invalid", "qos3"].iter().copied() {
+        let original = message(topic, b"payload");
+        assert_eq!(broker.message(&client, &original), Ok(original));
+    }
+    let warnings = broker
+        .logs()
+        .into_iter()
+        .filter(|log| {
+            log.level == LOG_WARNING
+                && log.message == "Failed to replace message in flight, passing it unchanged: Inval"
+        })
+        .count();
+    assert_eq!(warnings, 2);
+}
+
+#[test]
+fn returns_extended_auth_data() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    assert_eq!(
+        broker.auth_start(&client, "echo", Some(b"challenge")),
+        AuthOutcome::Continue(b"challenge".to_vec())
+    );
+    assert_eq!(
+        broker.auth_continue(&client, "echo", Some(b"wrong")),
+        AuthOutcome::Deny
+    );
+    assert_eq!(
+        broker.auth_start(&client, "unknown", None),
+        AuthOutcome::Deny
+    );
+
+    // More than an AUTH packet holds denies the client
+    assert_eq!(
+        broker.auth_start(&client, "oversized", None),
+        AuthOutcome::Deny
+    );
+}
+
+#[test]
+fn tells_reauthentication_of_authenticated_clients() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    broker.auth_start(&client, "echo", Some(b"challenge"));
+    assert_eq!(
+        broker.auth_continue(&client, "echo", Some(b"challenge")),
+        AuthOutcome::Accept(Some(b"welcome".to_vec()))
+    );
+    assert_eq!(
+        broker.auth_start(&client, "echo", None),
+        AuthOutcome::Accept(Some(b"welcome back".to_vec()))
+    );
+
+    // A new connection authenticates from the start
+    broker.disconnect(&client, DisconnectReason::ClientDisconnect);
+    assert_eq!(
+        broker.auth_start(&client, "echo", None),
+        AuthOutcome::Continue(Vec::new())
+    );
+}
+
+#[test]
+fn records_publishes_and_kicks() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    broker.tick();
+    assert!(broker.kicks().is_empty());
+    broker.tick();
+    assert_eq!(
+        broker.kicks(),
+        vec![Kick::Username {
+            username: "user".to_string(),
+            with_will: true,
+        }]
+    );
+
+    broker.disconnect(&client, DisconnectReason::ClientDisconnect);
+    let published = broker.take_published();
+    assert_eq!(published.len(), 1);
+    assert_eq!(published[0].topic, "left");
+    assert_eq!(published[0].payload, b"client");
+    assert!(broker.published().is_empty());
+
+    broker.disconnect(&client, DisconnectReason::KeepaliveTimeout);
+    assert_eq!(broker.take_published()[0].topic, "lost");
+}
+
+#[test]
+fn unloads_once() {
+    let mut broker = MockBroker::new();
+    let plugin = broker.load_plugin(&[]).unwrap();
+    assert_eq!(broker.unload_plugin(plugin), Ok(Success));
+    assert_eq!(broker.unload_plugin(plugin), Err(Error::NotFound));
+}
+
+#[test]
+fn refuses_to_load_with_invalid_options() {
+    let mut broker = MockBroker::new();
+    assert_eq!(broker.load_plugin(&[("topic", "")]), Err(Error::Inval));
+    assert!(
+        broker
+            .logs()
+            .iter()
+            .any(|log| log.level == LOG_ERR
+                && log.message == "plugin init failed: the topic is empty")
+    );
+
+    // Nothing was registered, no plugin decides
+    let client = FakeClient::new("client");
+    let message = OwnedMessage {
+        topic: "denied".to_string(),
+        payload: Vec::new(),
+        qos: 0,
+        retain: false,
+        properties: None,
+    };
+    assert_eq!(
+        broker.acl_check(&client, AclCheckAccessLevel::Write, &message),
+        Err(Error::PluginDefer)
+    );
+}
+
+#[test]
+fn refuses_to_load_without_its_access_checks() {
+    let mut broker = MockBroker::new();
+    broker.fail_registrations(MosquittoPluginEvent::MosqEvtAclCheck, Error::NoMem);
+    assert_eq!(broker.load_plugin(&[]), Err(Error::NoMem));
+    assert!(broker.logs().iter().any(|log| log.level == LOG_ERR
+        && log.message
+            == "plugin init failed: failed to register the callback for MosqEvtAclCheck"));
+
+    // The callbacks registered before are unregistered again
+    assert!(broker.registered_events().is_empty());
+}
+
+#[test]
+fn cleans_up_after_a_failed_init() {
+    let mut broker = MockBroker::new();
+    assert_eq!(broker.load_plugin(&[("topic", "")]), Err(Error::Inval));
+    broker.fail_registrations(MosquittoPluginEvent::MosqEvtAclCheck, Error::NoMem);
+    assert_eq!(broker.load_plugin(&[("topic", "t")]), Err(Error::NoMem));
+
+    // Cleanup succeeded without an instance, so the cleanup of the plugin never ran
+    assert!(broker.published().is_empty());
+    assert_eq!(
+        broker
+            .logs()
+            .iter()
+            .filter(|log| log.message == "cleaning up a plugin that failed to initialize")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn swaps_the_instance_only_after_a_successful_rebuild() {
+    let mut broker = MockBroker::new();
+    let plugin = broker.load_plugin(&[("topic", "first")]).unwrap();
+    let client = FakeClient::new("client");
+    let message = |topic: &str| OwnedMessage {
+        topic: topic.to_string(),
+        payload: Vec::new(),
+        qos: 0,
+        retain: false,
+        properties: None,
+    };
+    let write = AclCheckAccessLevel::Write;
+
+    assert_eq!(broker.reload(plugin, &[("topic", "second")]), Ok(Success));
+    assert_eq!(
+        broker.acl_check(&client, write, &message("second")),
+        Ok(Success)
+    );
+    assert_eq!(
+        broker.acl_check(&client, write, &message("first")),
+        Err(Error::AclDenied)
+    );
+
+    // The invalid options are refused and the previous instance keeps running
+    assert_eq!(broker.reload(plugin, &[("topic", "")]), Err(Error::Inval));
+    assert!(broker.logs().iter().any(|log| log.level == LOG_ERR
+        && log.message
+            == "plugin reload failed, keeping the previous options: the topic is empty"));
+    assert_eq!(
+        broker.acl_check(&client, write, &message("second")),
+        Ok(Success)
+    );
+}
+
+#[test]
+fn runs_scheduled_tasks_on_ticks() {
+    let mut broker = MockBroker::new();
+    broker
+        .load_plugin(&[("topic", "beat"), ("heartbeat", "10")])
+        .unwrap();
+    let heartbeats = |broker: &mut MockBroker| {
+        broker
+            .take_published()
+            .into_iter()
+            .filter(|message| message.topic == "heartbeat")
+            .count()
+    };
+
+    broker.tick_at(Duration::from_secs(1000));
+    broker.tick_at(Duration::from_millis(1_009_900));
+    assert_eq!(heartbeats(&mut broker), 0);
+    broker.tick_at(Duration::from_secs(1010));
+    assert_eq!(heartbeats(&mut broker), 1);
+    broker.tick_at(Duration::from_secs(1015));
+    assert_eq!(heartbeats(&mut broker), 0);
+    broker.tick_at(Duration::from_secs(1020));
+    assert_eq!(heartbeats(&mut broker), 1);
+}
+
+#[test]
+fn writes_psk_keys_into_the_buffer() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    assert_eq!(
+        broker.psk_key(&client, "hint", "sensor", 64),
+        Ok("deadbeef".to_string())
+    );
+    assert_eq!(
+        broker.psk_key(&client, "hint", "unknown", 64),
+        Err(Error::PluginDefer)
+    );
+    assert_eq!(
+        broker.psk_key(&client, "hint", "oversized", 64),
+        Err(Error::Inval)
+    );
+    assert!(broker.logs().iter().any(|log| log.level == LOG_WARNING
+        && log.message == "the PSK key does not fit the 64 hex characters mosquitto allows"));
+}
+
+#[test]
+fn answers_on_each_control_topic() {
+    let mut broker = MockBroker::new();
+    let plugin = broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("admin");
+    assert!(broker.logs().iter().any(|log| log.level == LOG_WARNING
+        && log.message == "failed to register callback for MosqEvtControl on not-control: Inval"));
+
+    for version in ["v1", "v2"] {
+        let topic = format!("$CONTROL/test-plugin/{}", version);
+        assert_eq!(broker.control(&client, &topic, b"ping"), Ok(Success));
+        let published = broker.take_published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].client_id.as_deref(), Some("admin"));
+        assert_eq!(published[0].topic, format!("{}/response", topic));
+        assert_eq!(
+            published[0].payload,
+            format!("pong from {}", topic).into_bytes()
+        );
+    }
+
+    // No response without one from the handler, nothing registered for other topics
+    assert_eq!(
+        broker.control(&client, "$CONTROL/test-plugin/v1", b"quiet"),
+        Ok(Success)
+    );
+    assert!(broker.take_published().is_empty());
+    assert_eq!(
+        broker.control(&client, "$CONTROL/test-plugin/v3", b"ping"),
+        Err(Error::NotFound)
+    );
+
+    broker.unload_plugin(plugin).unwrap();
+    assert_eq!(
+        broker.control(&client, "$CONTROL/test-plugin/v1", b"ping"),
+        Err(Error::NotFound)
+    );
+}
+
+#[test]
+fn keeps_instances_isolated() {
+    let mut broker = MockBroker::new();
+    let first = broker.load_plugin(&[("topic", "first")]).unwrap();
+    let second = broker.load_plugin(&[("topic", "second")]).unwrap();
+    let client = FakeClient::new("client");
+    let message = |topic: &str| OwnedMessage {
+        topic: topic.to_string(),
+        payload: Vec::new(),
+        qos: 0,
+        retain: false,
+        properties: None,
+    };
+
+    // Each instance counts its own ticks and kicks on its second one
+    broker.tick();
+    broker.tick();
+    assert_eq!(broker.kicks().len(), 2);
+
+    // The first instance decides the access checks while it is loaded
+    assert_eq!(
+        broker.acl_check(&client, AclCheckAccessLevel::Write, &message("second")),
+        Err(Error::AclDenied)
+    );
+    broker.unload_plugin(first).unwrap();
+    assert_eq!(
+        broker.acl_check(&client, AclCheckAccessLevel::Write, &message("second")),
+        Ok(Success)
+    );
+
+    broker.unload_plugin(second).unwrap();
+    let published = broker.take_published();
+    let unloaded: Vec<_> = published.iter().map(|m| &m.payload[..]).collect();
+    assert_eq!(unloaded, [&b"first"[..], &b"second"[..]]);
+    assert!(broker.registered_events().is_empty());
+}
+
+#[test]
+fn unregisters_and_cleans_up_on_unload() {
+    let mut broker = MockBroker::new();
+    let plugin = broker.load_plugin(&[("topic", "sensors")]).unwrap();
+    assert!(!broker.registered_events().is_empty());
+
+    broker.unload_plugin(plugin).unwrap();
+    assert!(broker.registered_events().is_empty());
+    let published = broker.take_published();
+    assert_eq!(published.len(), 1);
+    assert_eq!(published[0].topic, "unloaded");
+    assert_eq!(published[0].payload, b"sensors");
+}
+
+#[test]
+fn registers_only_the_declared_events() {
+    use MosquittoPluginEvent::*;
+
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+
+    #[allow(unused_mut)]
+    let mut expected = vec![
+        MosqEvtReload,
+        MosqEvtAclCheck,
+        MosqEvtBasicAuth,
+        MosqEvtExtAuthStart,
+        MosqEvtExtAuthContinue,
+        MosqEvtControl,
+        MosqEvtControl,
+        MosqEvtMessage,
+        MosqEvtPskKey,
+        MosqEvtTick,
+        MosqEvtDisconnect,
+    ];
+    #[cfg(feature = "mosq-2-1")]
+    expected.push(MosqEvtSubscribe);
+    let expected: Vec<i32> = expected.into_iter().map(i32::from).collect();
+    assert_eq!(broker.registered_events(), expected);
+}
+
+#[cfg(feature = "mosq-2-1")]
+#[test]
+fn reports_info_and_checks_subscriptions() {
+    let mut broker = MockBroker::new();
+    let plugin = broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+
+    assert_eq!(
+        broker.plugin_info(plugin),
+        Some(("test-plugin".to_string(), None))
+    );
+    assert_eq!(broker.subscribe(&client, "sensors/+", 1), Ok(Success));
+    assert_eq!(
+        broker.subscribe(&client, "sensors/+", 2),
+        Err(Error::AclDenied)
+    );
+    assert_eq!(broker.subscribe(&client, "#", 0), Err(Error::AclDenied));
+    assert_eq!(broker.unsubscribe(&client, "#"), Ok(Success));
+}
+
+#[cfg(feature = "mosq-2-1")]
+#[test]
+fn checks_access_per_listener() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let message = OwnedMessage {
+        topic: "any".to_string(),
+        payload: Vec::new(),
+        qos: 0,
+        retain: false,
+        properties: None,
+    };
+
+    let public = FakeClient::new("public").port(8883);
+    let internal = FakeClient::new("internal").port(1884);
+    assert_eq!(
+        broker.acl_check(&public, AclCheckAccessLevel::Write, &message),
+        Err(Error::AclDenied)
+    );
+    assert_eq!(
+        broker.acl_check(&internal, AclCheckAccessLevel::Write, &message),
+        Ok(Success)
+    );
+}
+
+#[cfg(feature = "mosq-2-1")]
+#[test]
+fn authenticates_per_listener() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+
+    let public = FakeClient::new("public").port(8883);
+    let internal = FakeClient::new("internal").port(1884);
+    assert_eq!(
+        broker.basic_auth(&public, Some(b"operator"), None),
+        Err(Error::Auth)
+    );
+    assert_eq!(
+        broker.basic_auth(&internal, Some(b"operator"), None),
+        Ok(Success)
+    );
+}
+
+const LOG_WARNING: i32 = mosquitto_dev::MOSQ_LOG_WARNING as i32;
+const LOG_ERR: i32 = mosquitto_dev::MOSQ_LOG_ERR as i32;
@@ -0,0 +1,110 @@
+use mosquitto_plugin::*;
+use mosquitto_plugin_testing::{FakeClient, MockBroker};
+
+// The mock broker links against the exports of a plugin, these tests don't load it
+#[derive(Debug)]
+struct Plugin;
+
+impl MosquittoPlugin for Plugin {
+    fn init(_opts: MosquittoOpt) -> Result<Self, InitError> {
+        Ok(Plugin)
+    }
+}
+
+create_dynamic_library!(Plugin);
+
+#[test]
+fn reads_the_same_directly_and_through_the_broker_functions() {
+    let _broker = MockBroker::new();
+    let fake = FakeClient::new("client")
+        .username("user")
+        .address("::1".parse().unwrap())
+        .protocol(MosquittoClientProtocol::Websockets)
+        .protocol_version(MosquittoClientProtocolVersion::V3)
+        .port(8080)
+        .keepalive(30)
+        .clean_session(false)
+        .sub_count(4);
+    let client = MosquittoClient {
+        client: fake.as_ptr(),
+    };
+
+    let contexts: [&dyn MosquittoClientContext; 2] = [&fake, &client];
+    for context in contexts.iter() {
+        assert_eq!(context.get_id().as_deref(), Some("client"));
+        assert_eq!(context.get_username().as_deref(), Some("user"));
+        assert_eq!(context.get_address(), Some("::1".parse().unwrap()));
+        assert_eq!(context.get_protocol(), MosquittoClientProtocol::Websockets);
+        assert_eq!(
+            context.get_protocol_version(),
+            MosquittoClientProtocolVersion::V3
+        );
+        assert_eq!(context.get_keepalive(), 30);
+        assert!(!context.is_clean_session());
+        assert_eq!(context.get_sub_count(), 4);
+        #[cfg(feature = "mosq-2-0")]
+        assert_eq!(
+            context.get_listener(),
+            Some(Listener {
+                port: None,
+                protocol: MosquittoClientProtocol::Websockets
+            })
+        );
+        #[cfg(feature = "mosq-2-1")]
+        assert_eq!(
+            context.get_listener(),
+            Some(Listener {
+                port: Some(8080),
+                protocol: MosquittoClientProtocol::Websockets
+            })
+        );
+    }
+}
+
+#[test]
+fn reads_unexpected_values_without_panicking() {
+    let _broker = MockBroker::new();
+    let fake = FakeClient::new("client")
+        .id_bytes(b"id\xff")
+        .username_bytes(b"user\xfe")
+        .address_str("/var/run/mosquitto.sock")
+        .protocol(MosquittoClientProtocol::Unknown(7))
+        .protocol_version(MosquittoClientProtocolVersion::Unknown(6));
+    let client = MosquittoClient {
+        client: fake.as_ptr(),
+    };
+
+    let contexts: [&dyn MosquittoClientContext; 2] = [&fake, &client];
+    for context in contexts.iter() {
+        assert_eq!(context.get_id(), None);
+        assert_eq!(context.get_id_bytes(), Some(b"id\xff".to_vec()));
+        assert_eq!(context.get_username(), None);
+        assert_eq!(context.get_username_bytes(), Some(b"user\xfe".to_vec()));
+        assert_eq!(context.get_address(), None);
+        assert_eq!(context.get_protocol(), MosquittoClientProtocol::Unknown(7));
+        assert_eq!(
+            context.get_protocol_version(),
+            MosquittoClientProtocolVersion::Unknown(6)
+        );
+    }
+    assert_eq!(client.set_username("nul\0".to_string()), Err(Error::Inval));
+}
+
+#[test]
+fn records_set_username() {
+    let _broker = MockBroker::new();
+    let fake = FakeClient::new("client").username("user");
+    let client = MosquittoClient {
+        client: fake.as_ptr(),
+    };
+
+    fake.set_username("first".to_string()).unwrap();
+    client.set_username("second".to_string()).unwrap();
+    assert_eq!(fake.set_username("nul\0".to_string()), Err(Error::Inval));
+
+    assert_eq!(fake.get_username().as_deref(), Some("second"));
+    assert_eq!(
+        fake.set_username_calls(),
+        vec![Some("first".to_string()), Some("second".to_string())]
+    );
+}
@@ -0,0 +1,113 @@
+// A plugin with the Defer panic policy. It needs a test binary of its own, since
+// create_dynamic_library! exports fixed symbol names and tests/broker.rs holds the Deny plugin.
+
+use mosquitto_plugin::*;
+use mosquitto_plugin_testing::{FakeClient, MockBroker};
+
+#[derive(Debug)]
+struct Panicking;
+
+impl MosquittoPlugin for Panicking {
+    const EVENTS: EventSet = EventSet::ACL_CHECK
+        .union(EventSet::BASIC_AUTH)
+        .union(EventSet::PSK_KEY)
+        .union(EventSet::MESSAGE)
+        .union(EventSet::TICK);
+    const PANIC_POLICY: PanicPolicy = PanicPolicy::Defer;
+
+    fn init(_opts: MosquittoOpt) -> Result<Self, InitError> {
+        Ok(Panicking)
+    }
+
+    fn acl_check(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
+        _level: AclCheckAccessLevel,
+        _msg: MosquittoMessage,
+    ) -> Result<Success, Error> {
+        panic!("acl_check")
+    }
+
+    fn username_password(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _listener: Option<Listener>,
+        _username: Option<&[u8]>,
+        _password: Option<&[u8]>,
+    ) -> Result<Success, Error> {
+        panic!("username_password")
+    }
+
+    fn on_psk(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _hint: &std::ffi::CStr,
+        _identity: &std::ffi::CStr,
+    ) -> Option<PskKey> {
+        panic!("on_psk")
+    }
+
+    fn on_message(
+        &mut self,
+        _client: &dyn MosquittoClientContext,
+        _message: MosquittoMessage,
+    ) -> MessageAction {
+        panic!("on_message")
+    }
+
+    fn on_tick(&mut self, _now_ns: i64, _next_ns: i64, _now_s: i32, _next_s: i32) {
+        panic!("on_tick")
+    }
+}
+
+create_dynamic_library!(Panicking);
+
+const LOG_ERR: i32 = mosquitto_dev::MOSQ_LOG_ERR as i32;
+
+#[test]
+fn defers_decisions_and_passes_messages_on_panic() {
+    let mut broker = MockBroker::new();
+    broker.load_plugin(&[]).unwrap();
+    let client = FakeClient::new("client");
+    let message = OwnedMessage {
+        topic: "sensors".to_string(),
+        payload: b"21.5".to_vec(),
+        qos: 1,
+        retain: false,
+        properties: None,
+    };
+
+    assert_eq!(
+        broker.acl_check(&client, AclCheckAccessLevel::Write, &message),
+        Err(Error::PluginDefer)
+    );
+    assert_eq!(
+        broker.basic_auth(&client, Some(b"user"), Some(b"pass")),
+        Err(Error::PluginDefer)
+    );
+    assert_eq!(
+        broker.psk_key(&client, "hint", "sensor", 64),
+        Err(Error::PluginDefer)
+    );
+    // Deferring would make mosquitto 2.0 disconnect the publisher
+    assert_eq!(broker.message(&client, &message), Ok(message.clone()));
+    broker.tick();
+
+    let panics: Vec<_> = broker
+        .logs()
+        .into_iter()
+        .filter(|log| log.level == LOG_ERR)
+        .map(|log| log.message)
+        .collect();
+    assert_eq!(
+        panics,
+        [
+            "plugin panicked in acl_check: acl_check",
+            "plugin panicked in username_password: username_password",
+            "plugin panicked in on_psk: on_psk",
+            "plugin panicked in on_message: on_message",
+            "plugin panicked in on_tick: on_tick",
+        ]
+    );
+}